@@ -0,0 +1,101 @@
+//! Parsing traces in formats common in Website Fingerprinting datasets.
+
+use std::{io::BufRead, time::Duration};
+
+use crate::{
+    parse::{ParseArgs, ParseError, ParseErrorKind, QueueBuilder},
+    queue::SimQueue,
+};
+
+/// The size of a Tor cell on the wire, in bytes.
+pub const TOR_CELL_SIZE: u16 = 514;
+
+/// The format of a trace in a Website Fingerprinting dataset. In all formats,
+/// each line is a timestamp in (fractional) seconds and a signed value,
+/// separated by a tab or spaces. A positive value is a packet sent by the
+/// client, a negative value a packet received by the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatasetFormat {
+    /// "timestamp\tdirection", where direction is 1 or -1, with every packet
+    /// of the given size.
+    Direction { size: u16 },
+    /// Tor cell-level traces, "timestamp\tdirection", where direction is
+    /// signed (typically 1 or 514) and every cell is [`TOR_CELL_SIZE`] bytes.
+    TorCells,
+    /// "timestamp\tsize", where size is the signed size of the packet.
+    SignedSize,
+}
+
+/// Parse a trace in a Website Fingerprinting dataset format into a
+/// [`SimQueue`], like [`parse_trace_advanced`](crate::parse_trace_advanced).
+///
+/// Timestamps are exact to the nanosecond (not rounded through floating
+/// point) and relative to the first line, which need not be at zero. A line
+/// with a timestamp before the first line is an error. Empty lines are
+/// ignored.
+pub fn parse_dataset<R: BufRead>(
+    reader: R,
+    format: DatasetFormat,
+    args: &ParseArgs,
+) -> Result<SimQueue, ParseError> {
+    let mut queue = QueueBuilder::new(args);
+    let mut start: Option<Duration> = None;
+    for (i, l) in reader.lines().enumerate() {
+        let err = |kind| ParseError { line: i + 1, kind };
+        let l = l.map_err(|e| err(ParseErrorKind::Io(e.kind())))?;
+        let parts: Vec<&str> = l.split_whitespace().collect();
+        if parts.is_empty() {
+            continue;
+        }
+        if parts.len() != 2 {
+            return Err(err(ParseErrorKind::InvalidFormat(l.trim().to_string())));
+        }
+
+        let timestamp = parse_seconds(parts[0])
+            .ok_or_else(|| err(ParseErrorKind::InvalidTimestamp(parts[0].to_string())))?;
+        let offset = timestamp
+            .checked_sub(*start.get_or_insert(timestamp))
+            .ok_or_else(|| err(ParseErrorKind::InvalidTimestamp(parts[0].to_string())))?;
+
+        let (sent, size) = match format {
+            DatasetFormat::Direction { size } => match parts[1] {
+                "1" | "+1" => (true, size),
+                "-1" => (false, size),
+                d => return Err(err(ParseErrorKind::InvalidDirection(d.to_string()))),
+            },
+            DatasetFormat::TorCells => match parts[1].parse::<i64>() {
+                Ok(d) if d != 0 => (d > 0, TOR_CELL_SIZE),
+                _ => return Err(err(ParseErrorKind::InvalidDirection(parts[1].to_string()))),
+            },
+            DatasetFormat::SignedSize => {
+                let signed = parts[1].parse::<i32>().ok().filter(|s| *s != 0);
+                match signed.map(|s| (s > 0, u16::try_from(s.unsigned_abs()))) {
+                    Some((sent, Ok(size))) => (sent, size),
+                    _ => return Err(err(ParseErrorKind::InvalidSize(parts[1].to_string()))),
+                }
+            }
+        };
+
+        queue.push(offset, sent, false, size, 0).map_err(err)?;
+    }
+    Ok(queue.finish())
+}
+
+// parse non-negative (fractional) seconds exactly into a duration, falling
+// back to floating point for, e.g., scientific notation
+fn parse_seconds(s: &str) -> Option<Duration> {
+    let (secs, frac) = s.split_once('.').unwrap_or((s, ""));
+    let digits = |d: &str| d.bytes().all(|b| b.is_ascii_digit());
+    if !(secs.is_empty() && frac.is_empty()) && digits(secs) && digits(frac) {
+        let secs = if secs.is_empty() {
+            0
+        } else {
+            secs.parse::<u64>().ok()?
+        };
+        // nanoseconds are the first nine digits of the fraction, truncating
+        let nanos = format!("{:0<9}", &frac[..frac.len().min(9)]);
+        return Some(Duration::new(secs, nanos.parse().ok()?));
+    }
+    let secs = s.parse::<f64>().ok()?;
+    Duration::try_from_secs_f64(secs).ok()
+}
@@ -1,12 +1,7 @@
 use rand::Rng;
-use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, error::Error, time::Duration};
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Bin {
-    range: (f64, f64),
-    probability: f64,
-}
+use crate::rng;
 
 /// Represents a Maybenot integration and its associated delays. This can happen
 /// in the case of Maybenot being integrated, e.g., in user space with a
@@ -98,22 +93,29 @@ impl BinDist {
         })
     }
 
+    /// The largest value that can be sampled.
+    pub fn max(&self) -> Duration {
+        let max = self.bins.iter().map(|b| b.1).fold(0.0, f64::max);
+        Duration::from_micros((max * 1000.0) as u64)
+    }
+
     pub fn sample(&self) -> Duration {
-        let mut rng = rand::thread_rng();
-        let sample_prob = rng.gen::<f64>();
-        let bin_index = match self
-            .cumulative_probabilities
-            .binary_search_by(|prob| prob.partial_cmp(&sample_prob).unwrap())
-        {
-            Ok(index) => index,
-            Err(index) => index,
-        };
-
-        let (min, max) = self.bins[bin_index];
-        // bins are in milliseconds, to get microseconds we multiply by 1000
-        if min == max {
-            return Duration::from_micros((min * 1000.0) as u64);
-        }
-        Duration::from_micros((rng.gen_range(min..max) * 1000.0) as u64)
+        rng::with_rng(|rng| {
+            let sample_prob = rng.gen::<f64>();
+            let bin_index = match self
+                .cumulative_probabilities
+                .binary_search_by(|prob| prob.partial_cmp(&sample_prob).unwrap())
+            {
+                Ok(index) => index,
+                Err(index) => index,
+            };
+
+            let (min, max) = self.bins[bin_index];
+            // bins are in milliseconds, to get microseconds we multiply by 1000
+            if min == max {
+                return Duration::from_micros((min * 1000.0) as u64);
+            }
+            Duration::from_micros((rng.gen_range(min..max) * 1000.0) as u64)
+        })
     }
 }
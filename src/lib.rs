@@ -4,13 +4,16 @@
 //! development of such defenses.
 //!
 //! The simulator consists of two core functions: [`parse_trace`] and [`sim`].
-//! The intended use is to first parse a trace (e.g., from a pcap file or a
-//! Website Fingerprinting dataset) using [`parse_trace`], and then simulate the
-//! trace using [`sim`] together with one or more Maybenot
+//! The intended use is to first parse a trace (e.g., from a Website
+//! Fingerprinting dataset) using [`parse_trace`] or
+//! [`dataset::parse_dataset`], or a pcap file using [`pcap::parse_pcap`], and
+//! then simulate the trace using [`sim`] together with one or more Maybenot
 //! [`Machines`](maybenot::machine::Machine) running at the client and/or
-//! server. The output of the simulator can then be parsed to produce a
-//! simulated trace that then in turn can be used to, e.g., train a Website
-//! Fingerprinting attack.
+//! server. The output of the simulator can then be written in the same format
+//! using [`format_trace`] to produce a simulated trace that then in turn can be
+//! used to, e.g., train a Website Fingerprinting attack. To single-step a
+//! simulation or stop it on custom conditions, use a [`Simulator`], which can
+//! also lazily yield the simulated trace in time order as a [`SimTrace`].
 //!
 //! ## Example usage
 //! ```
@@ -34,14 +37,15 @@
 //! 9420892765,r,191";
 //!
 //! // The network model for simulating the network between the client and the
-//! // server. Currently just a delay.
-//! let network = Network::new(Duration::from_millis(10));
+//! // server. Here just a delay, but the bandwidth can also be limited.
+//! let delay = Duration::from_millis(10);
+//! let network = Network::new(delay);
 //!
 //! // Parse the raw trace into a queue of events for the simulator. This uses
 //! // the delay to generate a queue of events at the client and server in such
 //! // a way that the client is ensured to get the packets in the same order and
 //! // at the same time as in the raw trace.
-//! let mut input_trace = parse_trace(raw_trace, &network);
+//! let mut input_trace = parse_trace(raw_trace, &network).unwrap();
 //!
 //! // A simple machine that sends one padding packet of 1000 bytes 20
 //! // milliseconds after the first NonPaddingSent is sent.
@@ -51,10 +55,10 @@
 //!
 //! // Run the simulator with the machine at the client. Run the simulation up
 //! // until 100 packets have been recorded (total, client and server).
-//! let trace = sim(&[m], &[], &mut input_trace, network.delay, 100, true);
+//! let trace = sim(&[m], &[], &mut input_trace, delay, 100, true).unwrap();
 //!
-//! // print packets from the client's perspective
-//! let starting_time = trace[0].time;
+//! // print packets from the client's perspective, with times since the start
+//! // of the trace
 //! trace
 //!     .into_iter()
 //!     .filter(|p| p.client)
@@ -63,28 +67,28 @@
 //!             println!(
 //!                 "sent {} bytes at {} ms",
 //!                 bytes_sent,
-//!                 (p.time - starting_time).as_millis()
+//!                 p.time.as_millis()
 //!             );
 //!         }
 //!         TriggerEvent::PaddingSent { bytes_sent, .. } => {
 //!             println!(
 //!                 "sent {} bytes of padding at {} ms",
 //!                 bytes_sent,
-//!                 (p.time - starting_time).as_millis()
+//!                 p.time.as_millis()
 //!             );
 //!         }
 //!         TriggerEvent::NonPaddingRecv { bytes_recv } => {
 //!             println!(
 //!                 "received {} bytes at {} ms",
 //!                 bytes_recv,
-//!                 (p.time - starting_time).as_millis()
+//!                 p.time.as_millis()
 //!             );
 //!         }
 //!         TriggerEvent::PaddingRecv { bytes_recv, .. } => {
 //!             println!(
 //!                 "received {} bytes of padding at {} ms",
 //!                 bytes_recv,
-//!                 (p.time - starting_time).as_millis()
+//!                 p.time.as_millis()
 //!             );
 //!         }
 //!         _ => {}
@@ -106,30 +110,51 @@
 //! received 191 bytes at 9420 ms
 //! ```
 
+pub mod dataset;
 pub mod integration;
 pub mod network;
+pub mod parse;
+pub mod pcap;
 pub mod peek;
 pub mod queue;
+pub mod relay;
+pub mod replace;
+pub mod rng;
+pub mod tap;
+pub mod time;
+pub mod transport;
+pub mod write;
 
 use std::{
     cmp::Reverse,
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt,
     time::{Duration, Instant},
 };
 
 use integration::Integration;
 use log::debug;
-use network::Network;
-use queue::SimQueue;
+use network::{Network, NetworkModel};
+use queue::{new_event, SimQueue};
+use relay::{path_delay, Relay};
+use replace::{EqualOrSmaller, ReplacePolicy, NETWORK_REPLACE_WINDOW};
+use tap::{Observed, Tap};
+use transport::{Tcp, Transport};
 
 use maybenot::{
     framework::{Action, Framework, MachineId, TriggerEvent},
     machine::Machine,
 };
+use rand::rngs::StdRng;
+
+pub use parse::{parse_trace, parse_trace_advanced, parse_trace_reader, ParseArgs};
+pub use write::{format_observed, format_trace, write_observed, write_trace};
 
 use crate::{
     network::sim_network_activity,
     peek::{peek_blocked_exp, peek_queue, peek_scheduled},
+    time::SimTime,
 };
 
 /// SimEvent represents an event in the simulator. It is used internally to
@@ -138,13 +163,30 @@ use crate::{
 #[derive(PartialEq, Hash, Eq, Clone, Debug)]
 pub struct SimEvent {
     pub event: TriggerEvent,
-    pub time: Instant,
+    pub time: SimTime,
     pub delay: Duration,
     pub client: bool,
+    /// true if the event is a sent packet that never arrived at the other
+    /// side: lost on the link or dropped by a full buffer
+    pub dropped: bool,
+    /// the flow (connection, e.g., a tunnel or circuit) of the event, set by
+    /// the [`Simulator`] for simulated events: each flow has its own machines
+    /// at the client and server
+    pub flow: usize,
+    /// the relay (numbered from 1 for the first relay after the client) the
+    /// event happened at, or None at the client or server (see `client`): at
+    /// a relay, `client` is true for events on its link toward the server
+    pub relay: Option<usize>,
     // internal flag to mark event as bypass
     bypass: bool,
     // internal flag to mark event as replace
     replace: bool,
+    // internal flag to mark event as input to the simulator (in the queue the
+    // simulator was created with)
+    input: bool,
+    // internal id of non-padding sent in causal replay, carried to where the
+    // packet is received (see ParseArgs::causal)
+    id: Option<u64>,
     // prevents collisions in simulator queue (see remove() instead of pop())
     fuzz: i32,
 }
@@ -154,21 +196,36 @@ pub struct SimEvent {
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct ScheduledAction {
     action: Option<Action>,
-    time: Instant,
+    time: SimTime,
+}
+
+impl ScheduledAction {
+    /// The action, if not yet done.
+    pub fn action(&self) -> Option<&Action> {
+        self.action.as_ref()
+    }
+
+    /// The time the action is scheduled for.
+    pub fn time(&self) -> SimTime {
+        self.time
+    }
 }
 
 /// The state of the client or the server in the simulator.
 pub struct SimState<M> {
     /// an instance of the Maybenot framework
     framework: Framework<M>,
+    /// the framework runs on wall-clock time: the instant and simulated time
+    /// when the framework was created, to map simulated time to instants
+    epoch: (Instant, SimTime),
     /// scheduled actions (timers)
     scheduled_action: HashMap<MachineId, ScheduledAction>,
     /// blocking time (active if in the future, relative to current_time)
-    blocking_until: Instant,
+    blocking_until: SimTime,
     /// whether the active blocking bypassable or not
     blocking_bypassable: bool,
     /// time of the last sent packet
-    last_sent_time: Instant,
+    last_sent_time: SimTime,
     /// size of the last sent packet
     last_sent_size: u16,
     /// integration aspects for this state
@@ -181,21 +238,16 @@ where
 {
     pub fn new(
         machines: M,
-        current_time: Instant,
+        current_time: SimTime,
         max_padding_frac: f64,
         max_blocking_frac: f64,
         mtu: u16,
         integration: Option<Integration>,
-    ) -> Self {
-        Self {
-            framework: Framework::new(
-                machines,
-                max_padding_frac,
-                max_blocking_frac,
-                mtu,
-                current_time,
-            )
-            .unwrap(),
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let epoch = (Instant::now(), current_time);
+        Ok(Self {
+            framework: Framework::new(machines, max_padding_frac, max_blocking_frac, mtu, epoch.0)?,
+            epoch,
             scheduled_action: HashMap::new(),
             // has to be in the past
             blocking_until: current_time.checked_sub(Duration::from_micros(1)).unwrap(),
@@ -206,7 +258,28 @@ where
                 .unwrap(),
             last_sent_size: 0,
             integration,
-        }
+        })
+    }
+
+    /// The instant in the framework for the simulated time.
+    fn instant(&self, time: SimTime) -> Instant {
+        self.epoch.0 + time.duration_since(self.epoch.1)
+    }
+
+    /// The time blocking ends at. Blocking is active if this is after the
+    /// current time.
+    pub fn blocking_until(&self) -> SimTime {
+        self.blocking_until
+    }
+
+    /// Whether the (active) blocking is bypassable.
+    pub fn blocking_bypassable(&self) -> bool {
+        self.blocking_bypassable
+    }
+
+    /// The scheduled actions (timers) of each machine.
+    pub fn scheduled_actions(&self) -> &HashMap<MachineId, ScheduledAction> {
+        &self.scheduled_action
     }
 
     pub fn reporting_delay(&self) -> Duration {
@@ -252,6 +325,9 @@ where
 /// are related to network activity (i.e., packets sent and received) to the
 /// output trace. This is recommended if you want to use the output trace for
 /// traffic analysis without further (recursive) simulation.
+///
+/// Returns an error if the machines cannot be run by the framework or the
+/// queue is empty, see [`SimError`].
 pub fn sim(
     machines_client: &[Machine],
     machines_server: &[Machine],
@@ -259,17 +335,68 @@ pub fn sim(
     delay: Duration,
     max_trace_length: usize,
     only_network_activity: bool,
-) -> Vec<SimEvent> {
+) -> Result<Vec<SimEvent>, SimError> {
     let network = Network::new(delay);
     let args = SimulatorArgs::new(&network, max_trace_length, only_network_activity);
     sim_advanced(machines_client, machines_server, sq, &args)
 }
 
+/// An error from running the simulator.
+#[derive(Debug)]
+pub enum SimError {
+    /// The framework could not be created for the client or server, e.g.,
+    /// because of an invalid machine or padding/blocking fraction.
+    Framework {
+        client: bool,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// The queue has no events to simulate.
+    EmptyQueue,
+    /// The next event is before the current time. This is a bug in the
+    /// simulator.
+    TimeMovedBackwards { current: SimTime, next: SimTime },
+    /// A tap is not on the path, i.e., a [`Tap::Relay`] not numbering one of
+    /// the relays.
+    InvalidTap(Tap),
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimError::Framework { client, source } => {
+                let side = if *client { "client" } else { "server" };
+                write!(f, "failed to create framework at {}: {}", side, source)
+            }
+            SimError::EmptyQueue => write!(f, "empty queue, nothing to simulate"),
+            SimError::TimeMovedBackwards { current, next } => write!(
+                f,
+                "BUG: next event moves time backwards by {:?}",
+                current.duration_since(*next)
+            ),
+            SimError::InvalidTap(tap) => write!(f, "invalid tap {:?}, not on the path", tap),
+        }
+    }
+}
+
+impl Error for SimError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SimError::Framework { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
 /// Arguments for [`sim_advanced`].
 #[derive(Clone, Debug)]
 pub struct SimulatorArgs<'a> {
-    pub network: &'a Network,
+    /// The model of the network between the client and server, e.g., a
+    /// [`Network`].
+    pub network: &'a dyn NetworkModel,
+    /// Stop after this many events in the output trace, if > 0. To stop at
+    /// the first events in time order, use [`Simulator::into_trace`] instead.
     pub max_trace_length: usize,
+    /// Stop after simulating this many events, if > 0.
     pub max_sim_iterations: usize,
     pub only_client_events: bool,
     pub only_network_activity: bool,
@@ -280,10 +407,44 @@ pub struct SimulatorArgs<'a> {
     pub mtu: u16,
     pub client_integration: Option<&'a Integration>,
     pub server_integration: Option<&'a Integration>,
+    /// The time window in which padding may be replaced by other packets, see
+    /// [`ReplacePolicy`].
+    pub replace_window: Duration,
+    /// The policy deciding if padding is replaced by other packets.
+    pub replace_policy: &'a dyn ReplacePolicy,
+    /// If set, seeds the RNG of the simulator, see [`rng`] for what is (and
+    /// is not) made reproducible.
+    pub seed: Option<u64>,
+    /// If set, stop once the simulated time passes the start of the trace
+    /// ([`SimTime::ZERO`]) plus this duration, e.g., to cut traces to a fixed
+    /// time window.
+    pub max_sim_duration: Option<Duration>,
+    /// If set, stop once the simulated time passes this duration after the
+    /// last input event (in the queue when the simulation started) was
+    /// simulated, e.g., to not simulate machines padding forever.
+    pub stop_after_last_input: Option<Duration>,
+    /// If set, non-padding is sent by a TCP-like transport with a congestion
+    /// window clocked by ACKs, see [`Tcp`].
+    pub transport: Option<Tcp>,
+    /// If true, all flows share one network (e.g., competing for the
+    /// bandwidth of its links), otherwise each flow has its own copy of the
+    /// network.
+    pub share_network: bool,
+    /// The relays on the path between the client and server, in order from
+    /// the client, see [`Relay`]. The [`network`](Self::network) is then the
+    /// network between the client and the first relay.
+    pub relays: &'a [Relay<'a>],
+    /// The observer taps recording the packets on the wire, see [`Tap`] and
+    /// [`Simulator::observed`].
+    pub taps: &'a [Tap],
 }
 
 impl<'a> SimulatorArgs<'a> {
-    pub fn new(network: &'a Network, max_trace_length: usize, only_network_activity: bool) -> Self {
+    pub fn new(
+        network: &'a dyn NetworkModel,
+        max_trace_length: usize,
+        only_network_activity: bool,
+    ) -> Self {
         Self {
             network,
             max_trace_length,
@@ -298,6 +459,15 @@ impl<'a> SimulatorArgs<'a> {
             mtu: 1420,
             client_integration: None,
             server_integration: None,
+            replace_window: NETWORK_REPLACE_WINDOW,
+            replace_policy: &EqualOrSmaller,
+            seed: None,
+            max_sim_duration: None,
+            stop_after_last_input: None,
+            transport: None,
+            share_network: false,
+            relays: &[],
+            taps: &[],
         }
     }
 }
@@ -305,59 +475,546 @@ impl<'a> SimulatorArgs<'a> {
 /// Like [`sim`], but allows to (i) set the maximum padding and blocking
 /// fractions for the client and server, (ii) specify the maximum number of
 /// iterations to run the simulator for, and (iii) only returning client events.
+///
+/// Runs a [`Simulator`] to completion. The events left in the queue when the
+/// simulator stops (e.g., on reaching the maximum trace length) are put back
+/// into the queue.
 pub fn sim_advanced(
     machines_client: &[Machine],
     machines_server: &[Machine],
     sq: &mut SimQueue,
     args: &SimulatorArgs,
-) -> Vec<SimEvent> {
+) -> Result<Vec<SimEvent>, SimError> {
+    let mut simulator = Simulator::new(machines_client, machines_server, std::mem::take(sq), args)?;
+
     // the resulting simulated trace
     let mut trace: Vec<SimEvent> = vec![];
+    let result = loop {
+        match simulator.step() {
+            Ok(Some(event)) => trace.push(event),
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    *sq = simulator.into_queue();
+    result?;
 
-    // put the mocked current time at the first event
-    let mut current_time = sq.peek().unwrap().0.time;
+    // sort the trace by time
+    trace.sort_by_key(|e| e.time);
 
-    // the client and server states
-    let mut client = SimState::new(
-        machines_client,
-        current_time,
-        args.max_padding_frac_client,
-        args.max_blocking_frac_client,
-        args.mtu,
-        args.client_integration.cloned(),
-    );
-    let mut server = SimState::new(
-        machines_server,
-        current_time,
-        args.max_padding_frac_server,
-        args.max_blocking_frac_server,
-        args.mtu,
-        args.server_integration.cloned(),
-    );
+    Ok(trace)
+}
+
+/// A simulator that can be run step by step, e.g., to single-step machines or
+/// to stop on custom conditions. Owns the queue and the state of the client
+/// and server. Use [`sim_advanced`] to run a simulation to completion.
+///
+/// The events in the queue may be in several flows (connections, e.g.,
+/// tunnels or circuits, see [`SimEvent::flow`]), simulated concurrently in
+/// time order. Each flow has its own client and server state, running its
+/// own instance of the machines, and its own congestion windows. The flows
+/// share the network if [`SimulatorArgs::share_network`] is set. To instead
+/// run one instance of the machines for all connections (e.g., a defense per
+/// host rather than per connection), put all packets in the same flow, see
+/// [`ParseArgs::single_flow`].
+///
+/// With [`SimulatorArgs::relays`], each flow takes a path of several hops
+/// from the client to the server, see [`Relay`].
+pub struct Simulator<'a> {
+    args: SimulatorArgs<'a>,
+    // the flows, in order of flow id
+    flows: Vec<Flow<'a>>,
+    // the network keeps state (e.g., packets queued on the link) during the
+    // simulation, so we work on a fresh copy for each simulator: the networks
+    // of each hop, shared by all flows or for each flow
+    networks: Vec<Vec<Box<dyn NetworkModel>>>,
+    current_time: SimTime,
+    start_time: SimTime,
+    sim_iterations: usize,
+    trace_length: usize,
+    // the number of input events yet to be simulated
+    input_left: usize,
+    // the time the last input event was simulated at, once simulated
+    last_input_time: Option<SimTime>,
+    // the packets seen at each tap, in time order
+    observed: Vec<Vec<Observed>>,
+    // all randomness sampled by this simulator
+    rng: StdRng,
+}
+
+// a flow in a simulation, over the hops of the path from the client to the
+// server
+struct Flow<'a> {
+    // the id of the flow, as in SimEvent::flow
+    id: usize,
+    hops: Vec<Hop<'a>>,
+    // the congestion windows of the client and server, if any
+    transport: Option<Transport>,
+}
+
+// a hop on the path of a flow: the queue of events at, and the state of, the
+// client (or relay) and server (or relay) at the ends of one network
+struct Hop<'a> {
+    sq: SimQueue,
+    client: SimState<&'a [Machine]>,
+    server: SimState<&'a [Machine]>,
+}
+
+impl Hop<'_> {
+    // the time the next event happens, without picking it (which may act on
+    // scheduled actions), or None if there is none
+    fn next_time(&self, current_time: SimTime) -> Option<SimTime> {
+        let s = peek_scheduled(
+            &self.client.scheduled_action,
+            &self.server.scheduled_action,
+            current_time,
+        );
+        let b = peek_blocked_exp(
+            &self.client.blocking_until,
+            &self.server.blocking_until,
+            current_time,
+        );
+        let (q, _) = peek_queue(&self.sq, &self.client, &self.server, s.min(b), current_time);
+        let earliest = s.min(b).min(q);
+        (earliest != Duration::MAX).then(|| current_time + earliest)
+    }
+}
+
+impl<'a> Simulator<'a> {
+    /// Create a simulator for the queue, with time at the first event in the
+    /// queue. Every flow in the queue runs the machines. Seeds the RNG if
+    /// [`SimulatorArgs::seed`] is set.
+    ///
+    /// Returns an error if the machines cannot be run by the framework or the
+    /// queue is empty, see [`SimError`].
+    pub fn new(
+        machines_client: &'a [Machine],
+        machines_server: &'a [Machine],
+        sq: SimQueue,
+        args: &SimulatorArgs<'a>,
+    ) -> Result<Self, SimError> {
+        Self::build(sq, args, |_| (machines_client, machines_server))
+    }
+
+    /// Like [`new`](Self::new), but with different machines for each flow:
+    /// the flow with id i runs the client and server machines in
+    /// `machines[i]`, and flows with ids after the last machines run none,
+    /// e.g., as undefended connections.
+    pub fn new_per_flow(
+        machines: &[(&'a [Machine], &'a [Machine])],
+        sq: SimQueue,
+        args: &SimulatorArgs<'a>,
+    ) -> Result<Self, SimError> {
+        Self::build(sq, args, |flow| {
+            machines.get(flow).copied().unwrap_or((&[], &[]))
+        })
+    }
+
+    fn build(
+        mut sq: SimQueue,
+        args: &SimulatorArgs<'a>,
+        machines: impl Fn(usize) -> (&'a [Machine], &'a [Machine]),
+    ) -> Result<Self, SimError> {
+        let hops = args.relays.len() + 1;
+        if let Some(tap) = args.taps.iter().find(|tap| tap.end(hops).is_none()) {
+            return Err(SimError::InvalidTap(*tap));
+        }
+
+        // put the mocked current time at the first event
+        let current_time = sq.peek().ok_or(SimError::EmptyQueue)?.0.time;
+
+        sq.mark_input();
+        let input_left = sq.len() + sq.dependent_len();
+
+        // the state of one end of a hop, for the errors of the client or
+        // server (relays are at the server end of the hop toward the client)
+        let state = |machines, padding, blocking, integration: Option<&Integration>, client| {
+            SimState::new(
+                machines,
+                current_time,
+                padding,
+                blocking,
+                args.mtu,
+                integration.cloned(),
+            )
+            .map_err(|source| SimError::Framework { client, source })
+        };
+
+        // the states of the client, relays, and server on each hop of the
+        // path of each flow
+        let mut flows = vec![];
+        for (id, sq) in sq.into_flows() {
+            let (machines_client, machines_server) = machines(id);
+            let mut hops = vec![];
+            let mut client = state(
+                machines_client,
+                args.max_padding_frac_client,
+                args.max_blocking_frac_client,
+                args.client_integration,
+                true,
+            )?;
+            for relay in args.relays {
+                let server = state(
+                    relay.client_machines,
+                    relay.max_padding_frac,
+                    relay.max_blocking_frac,
+                    relay.integration,
+                    false,
+                )?;
+                hops.push(Hop {
+                    sq: SimQueue::new(),
+                    client,
+                    server,
+                });
+                client = state(
+                    relay.server_machines,
+                    relay.max_padding_frac,
+                    relay.max_blocking_frac,
+                    relay.integration,
+                    false,
+                )?;
+            }
+            let server = state(
+                machines_server,
+                args.max_padding_frac_server,
+                args.max_blocking_frac_server,
+                args.server_integration,
+                false,
+            )?;
+            hops.push(Hop {
+                sq: SimQueue::new(),
+                client,
+                server,
+            });
+
+            // the client and server send from their ends of the path
+            if hops.len() == 1 {
+                hops[0].sq = sq;
+            } else {
+                let (client, server) = sq.into_sides();
+                hops[0].sq = client;
+                hops.last_mut().unwrap().sq = server;
+            }
+            flows.push(Flow {
+                id,
+                hops,
+                transport: args.transport.as_ref().map(Transport::new),
+            });
+        }
+
+        let networks = if args.share_network { 1 } else { flows.len() };
+        let path = || {
+            std::iter::once(args.network)
+                .chain(args.relays.iter().map(|r| r.network))
+                .map(|n| n.new_run())
+                .collect()
+        };
+
+        // the networks sample, e.g., the seeds for packet loss
+        let mut rng = rng::new(args.seed);
+        rng::swap(&mut rng);
+        let networks = (0..networks).map(|_| path()).collect();
+        rng::swap(&mut rng);
+
+        Ok(Self {
+            args: args.clone(),
+            flows,
+            networks,
+            current_time,
+            start_time: current_time,
+            sim_iterations: 0,
+            trace_length: 0,
+            input_left,
+            last_input_time: None,
+            observed: vec![vec![]; args.taps.len()],
+            rng,
+        })
+    }
+
+    /// Simulate events until the next event for the output trace, and return
+    /// it. Returns None once the simulation is done: nothing is left to
+    /// simulate, or the maximum trace length or number of iterations in the
+    /// [`SimulatorArgs`] is reached.
+    ///
+    /// Without `only_network_activity` and `only_client_events`, a step
+    /// usually simulates exactly one event and outputs it. With a
+    /// [`transport`](SimulatorArgs::transport), non-padding that has to wait
+    /// for room in the congestion window is not output when first simulated:
+    /// the step goes on with the next event, and the non-padding is output by
+    /// a later step, once it is sent. Events are output in the order they are
+    /// simulated: because of integration delays, their times in the output
+    /// trace are not necessarily in order.
+    pub fn step(&mut self) -> Result<Option<SimEvent>, SimError> {
+        self.with_rng(|sim| {
+            while let Some((flow, hop, next)) = sim.pick_next() {
+                if let Some(event) = sim.process(flow, hop, next)? {
+                    return Ok(Some(event));
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    /// Simulate all events that happen up to and including the time,
+    /// returning the events for the output trace as from
+    /// [`step`](Self::step).
+    pub fn run_until(&mut self, time: SimTime) -> Result<Vec<SimEvent>, SimError> {
+        self.with_rng(|sim| {
+            let mut events = vec![];
+            while sim.next_time().is_some_and(|t| t <= time) {
+                let Some((flow, hop, next)) = sim.pick_next() else {
+                    break;
+                };
+                // picking may skip ahead, e.g., past a cancelled action
+                if next.time > time {
+                    sim.flows[flow].hops[hop]
+                        .sq
+                        .push_sim(next.clone(), Reverse(next.time));
+                    break;
+                }
+                if let Some(event) = sim.process(flow, hop, next)? {
+                    events.push(event);
+                }
+            }
+            Ok(events)
+        })
+    }
+
+    // run f with the RNG of this simulator as the RNG of the current thread
+    fn with_rng<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        rng::swap(&mut self.rng);
+        let result = f(self);
+        rng::swap(&mut self.rng);
+        result
+    }
+
+    /// The current time of the simulation.
+    pub fn current_time(&self) -> SimTime {
+        self.current_time
+    }
+
+    /// The ids of the flows in the simulation, in order.
+    pub fn flows(&self) -> impl Iterator<Item = usize> + '_ {
+        self.flows.iter().map(|f| f.id)
+    }
+
+    /// The state of the client in the flow with the id, e.g., its blocking
+    /// and scheduled actions. Panics if there is no such flow.
+    pub fn client(&self, flow: usize) -> &SimState<&'a [Machine]> {
+        &self.flow(flow).hops[0].client
+    }
+
+    /// The state of the server in the flow with the id, e.g., its blocking
+    /// and scheduled actions. Panics if there is no such flow.
+    pub fn server(&self, flow: usize) -> &SimState<&'a [Machine]> {
+        &self.flow(flow).hops.last().unwrap().server
+    }
+
+    /// The queue of events yet to be simulated on the hop in the flow (hop 0
+    /// is from the client, and the only hop without relays). Panics if there
+    /// is no such flow or hop.
+    pub fn queue(&self, flow: usize, hop: usize) -> &SimQueue {
+        &self.flow(flow).hops[hop].sq
+    }
+
+    fn flow(&self, id: usize) -> &Flow<'a> {
+        let i = self.flows.binary_search_by_key(&id, |f| f.id);
+        &self.flows[i.expect("no flow with the id")]
+    }
+
+    /// The packets seen so far by the observer at the tap (by its index in
+    /// [`SimulatorArgs::taps`]), in time order. Packets that have not yet
+    /// been received by the end of the hop the tap is at are not seen yet.
+    /// Panics if there is no such tap.
+    pub fn observed(&self, tap: usize) -> &[Observed] {
+        &self.observed[tap]
+    }
+
+    /// Consume the simulator, returning the events yet to be simulated, in
+    /// their flows. With relays, this includes the events on all hops.
+    pub fn into_queue(self) -> SimQueue {
+        let mut sq = SimQueue::new();
+        for mut flow in self.flows {
+            // packets waiting for room in a congestion window have not been
+            // sent yet
+            if let Some(transport) = &mut flow.transport {
+                for waiting in transport.take_waiting() {
+                    let time = waiting.time;
+                    let hop = if waiting.client {
+                        0
+                    } else {
+                        flow.hops.len() - 1
+                    };
+                    flow.hops[hop].sq.push_sim(waiting, Reverse(time));
+                }
+            }
+            for hop in flow.hops {
+                sq.append_flow(hop.sq, flow.id);
+            }
+        }
+        sq
+    }
+
+    // true once the maximum trace length or number of iterations is reached
+    fn done(&self) -> bool {
+        if self.args.max_trace_length > 0 && self.trace_length >= self.args.max_trace_length {
+            debug!(
+                "sim(): we done, reached max trace length {}",
+                self.args.max_trace_length
+            );
+            return true;
+        }
+        if self.args.max_sim_iterations > 0 && self.sim_iterations >= self.args.max_sim_iterations {
+            debug!(
+                "sim(): we done, reached max sim iterations {}",
+                self.args.max_sim_iterations
+            );
+            return true;
+        }
+        false
+    }
+
+    // the time the next event happens on each hop of each flow, without
+    // picking it, by flow and hop
+    fn next_times(&self) -> impl Iterator<Item = (SimTime, usize, usize)> + '_ {
+        self.flows.iter().enumerate().flat_map(move |(i, f)| {
+            f.hops
+                .iter()
+                .enumerate()
+                .filter_map(move |(j, h)| Some((h.next_time(self.current_time)?, i, j)))
+        })
+    }
+
+    // the time the next event happens in any flow, without picking it, or
+    // None if the simulation is done
+    fn next_time(&self) -> Option<SimTime> {
+        if self.done() {
+            return None;
+        }
+        self.next_times().map(|(t, _, _)| t).min()
+    }
+
+    // pick the next event to simulate with its flow and hop, or None if the
+    // simulation is done
+    fn pick_next(&mut self) -> Option<(usize, usize, SimEvent)> {
+        if self.done() {
+            return None;
+        }
+        let current_time = self.current_time;
+        loop {
+            // the hop with the earliest next event, the first flow and hop on
+            // ties
+            let (_, i, j) = self.next_times().min()?;
+            let hops = self.flows[i].hops.len();
+            let hop = &mut self.flows[i].hops[j];
+            let Some(mut next) =
+                pick_next(&mut hop.sq, &mut hop.client, &mut hop.server, current_time)
+            else {
+                // e.g., only a cancelled action was left on the hop
+                continue;
+            };
+            next.flow = self.flows[i].id;
+            // the client is the first node on the path and the server the last
+            let node = if next.client { j } else { j + 1 };
+            next.relay = (node > 0 && node < hops).then_some(node);
+
+            // picking may skip ahead (e.g., past a cancelled action) of the
+            // next event on another hop or in another flow, or past the end
+            let other_before = self
+                .next_times()
+                .any(|(t, oi, oj)| (oi, oj) != (i, j) && t < next.time);
+            let end = self.end_time().is_some_and(|end| next.time > end);
+            if other_before || end {
+                let hop = &mut self.flows[i].hops[j];
+                hop.sq.push_sim(next.clone(), Reverse(next.time));
+            }
+            if other_before {
+                continue;
+            }
+            if end {
+                debug!("sim(): we done, reached the end time");
+                return None;
+            }
+            return Some((i, j, next));
+        }
+    }
+
+    // the time to stop after, from the maximum duration and the time after
+    // the last input event
+    fn end_time(&self) -> Option<SimTime> {
+        let max_duration = self.args.max_sim_duration.map(|d| SimTime::ZERO + d);
+        let after_input = self
+            .args
+            .stop_after_last_input
+            .zip(self.last_input_time)
+            .map(|(d, t)| t + d);
+        match (max_duration, after_input) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    // simulate the next event on the hop in the flow, returning it if it is
+    // part of the output trace
+    fn process(
+        &mut self,
+        flow: usize,
+        hop: usize,
+        mut next: SimEvent,
+    ) -> Result<Option<SimEvent>, SimError> {
+        let args = &self.args;
+        let Flow {
+            hops, transport, ..
+        } = &mut self.flows[flow];
+        let last = hops.len() - 1;
+        // flows share the only networks, if shared
+        let shared = self.networks.len() - 1;
+        let networks = &mut self.networks[flow.min(shared)];
+        let start_time = self.start_time;
+        // at the client or server rather than a relay
+        let endpoint = next.relay.is_none();
 
-    let mut sim_iterations = 0;
-    let start_time = current_time;
-    while let Some(next) = pick_next(sq, &mut client, &mut server, current_time) {
         debug!("#########################################################");
         debug!("sim(): main loop start, moving time forward");
 
         // move time forward
-        if next.time < current_time {
-            debug!("sim(): {:#?}", current_time);
-            debug!("sim(): {:#?}", next.time);
-            panic!("BUG: next event moves time backwards");
+        if next.time < self.current_time {
+            return Err(SimError::TimeMovedBackwards {
+                current: self.current_time,
+                next: next.time,
+            });
+        }
+        self.current_time = next.time;
+        let current_time = self.current_time;
+
+        // non-padding may have to wait for room in the congestion window
+        if let (Some(transport), TriggerEvent::NonPaddingSent { .. }, true) =
+            (&mut *transport, &next.event, endpoint)
+        {
+            let Some(sent) = transport.send(next, current_time, &mut hops[hop].sq) else {
+                self.sim_iterations += 1;
+                return Ok(None);
+            };
+            next = sent;
+        }
+
+        if next.input {
+            self.input_left -= 1;
+            if self.input_left == 0 {
+                self.last_input_time = Some(current_time);
+            }
         }
-        current_time = next.time;
         debug!(
             "sim(): at time {:#?}",
             current_time.duration_since(start_time)
         );
-        if next.client {
-            debug!("sim(): @client next\n{:#?}", next);
-        } else {
-            debug!("sim(): @server next\n{:#?}", next);
+        match (next.relay, next.client) {
+            (Some(relay), _) => debug!("sim(): @relay {} next\n{:#?}", relay, next),
+            (None, true) => debug!("sim(): @client next\n{:#?}", next),
+            (None, false) => debug!("sim(): @server next\n{:#?}", next),
         }
 
+        let Hop { sq, client, server } = &mut hops[hop];
         // if the client is blocked
         if client.blocking_until > current_time {
             debug!(
@@ -375,11 +1032,12 @@ pub fn sim_advanced(
         // For (non-)padding sent, queue the corresponding padding recv event:
         // in other words, where we simulate sending packets. The only place
         // where the simulator simulates the entire network between the client
-        // and the server. TODO: make delay/network more realistic.
+        // and the server (or the ends of a hop).
+        let network = networks[hop].as_mut();
         let network_activity = if next.client {
-            sim_network_activity(&next, sq, &client, &server, args.network, &current_time)
+            sim_network_activity(&mut next, sq, client, server, network, &current_time, args)
         } else {
-            sim_network_activity(&next, sq, &server, &client, args.network, &current_time)
+            sim_network_activity(&mut next, sq, server, client, network, &current_time, args)
         };
 
         if network_activity {
@@ -399,73 +1057,207 @@ pub fn sim_advanced(
             }
         }
 
+        // in causal replay, the packets sent in response to a lost packet that
+        // is never sent again are sent relative to when it would have been
+        // received, at the nominal delay of the rest of the path
+        if let (true, Some(id), TriggerEvent::NonPaddingSent { bytes_sent }) =
+            (next.dropped, next.id, &next.event)
+        {
+            if networks[hop].rto(next.client).is_none() {
+                let sent = current_time - next.delay;
+                let rest = if next.client {
+                    &networks[hop..]
+                } else {
+                    &networks[..=hop]
+                };
+                let since_start = sent.since_start().unwrap_or_default();
+                let received = sent + path_delay(rest, next.client, since_start, *bytes_sent);
+                let receiver = if next.client { last } else { 0 };
+                hops[receiver].sq.release(id, received);
+            }
+        }
+
+        // ACKs (or losses) open up the congestion window of the sender
+        if let Some(transport) = transport {
+            let ack = endpoint || matches!(next.event, TriggerEvent::NonPaddingSent { .. });
+            if ack {
+                transport.ack(
+                    &next,
+                    |client, time, size| path_delay(networks, client, time, size),
+                    |client| &mut hops[if client { 0 } else { last }].sq,
+                );
+            }
+        }
+
+        if let TriggerEvent::NonPaddingRecv { bytes_recv } = next.event {
+            let received = current_time - next.delay;
+            if endpoint {
+                // in causal replay, packets sent in response to the received
+                // packet are sent relative to when it was received
+                if let Some(id) = next.id {
+                    hops[hop].sq.release(id, received);
+                }
+            } else {
+                // relays forward non-padding to the next hop at once, as
+                // reported by the integration at the relay
+                let (hop, state) = if next.client {
+                    (hop - 1, &hops[hop - 1].server)
+                } else {
+                    (hop + 1, &hops[hop + 1].client)
+                };
+                let delay = state.reporting_delay();
+                let event = TriggerEvent::NonPaddingSent {
+                    bytes_sent: bytes_recv,
+                };
+                let time = (received + delay).max(current_time);
+                let forward = new_event(event, !next.client, time, delay, next.flow, next.id);
+                hops[hop].sq.push_sim(forward, Reverse(time));
+            }
+        }
+
+        // observers at this end of the hop see the packet on the wire
+        let observed = Observed::new(&next.event, next.client, trace_time(&next));
+        if let (true, Some(o)) = (network_activity, observed) {
+            for (tap, seen) in args.taps.iter().zip(&mut self.observed) {
+                if tap.end(last + 1) == Some((hop, next.client)) {
+                    let i = seen.partition_point(|s| s.time <= o.time);
+                    seen.insert(i, o);
+                }
+            }
+        }
+
         // get actions, update scheduled actions
+        let Hop { client, server, .. } = &mut hops[hop];
         if next.client {
             debug!("sim(): trigger @client framework\n{:#?}", next.event);
-            trigger_update(&mut client, &next, &current_time);
+            trigger_update(client, &next, &current_time);
         } else {
             debug!("sim(): trigger @server framework\n{:#?}", next.event);
-            trigger_update(&mut server, &next, &current_time);
+            trigger_update(server, &next, &current_time);
         }
+        self.sim_iterations += 1;
+
+        debug!("sim(): main loop end, more work?");
+        debug!("#########################################################");
 
         // conditional save to resulting trace: only on network activity if set
         // in fn arg, and only on client activity if set in fn arg
-        if (!args.only_network_activity || network_activity)
-            && (!args.only_client_events || next.client)
+        if (args.only_network_activity && !network_activity)
+            || (args.only_client_events && !(next.client && endpoint))
         {
-            // this should be a network trace: adjust timestamps based on any
-            // integration delays
-            let mut n = next.clone();
-            match next.event {
-                TriggerEvent::PaddingSent { .. } => {
-                    // padding adds the action delay
-                    n.time += n.delay;
-                }
-                TriggerEvent::PaddingRecv { .. }
-                | TriggerEvent::NonPaddingRecv { .. }
-                | TriggerEvent::NonPaddingSent { .. } => {
-                    // reported events remove the reporting delay
-                    n.time -= n.delay;
-                }
-                _ => {}
-            }
-
-            trace.push(n);
+            return Ok(None);
         }
 
-        if args.max_trace_length > 0 && trace.len() >= args.max_trace_length {
-            debug!(
-                "sim(): we done, reached max trace length {}",
-                args.max_trace_length
-            );
-            break;
-        }
+        // this should be a network trace: adjust timestamps based on any
+        // integration delays
+        next.time = trace_time(&next);
+        self.trace_length += 1;
 
-        // check if we should stop
-        sim_iterations += 1;
-        if args.max_sim_iterations > 0 && sim_iterations >= args.max_sim_iterations {
-            debug!(
-                "sim(): we done, reached max sim iterations {}",
-                args.max_sim_iterations
-            );
-            break;
+        Ok(Some(next))
+    }
+}
+
+/// The output trace of a [`Simulator`] as an iterator, yielding events in time
+/// order as they are simulated. Created by [`Simulator::into_trace`].
+///
+/// Events are only simulated as the iterator is advanced, so the consumer can
+/// stop whenever it wants (e.g., with [`Iterator::take`]), also for machines
+/// that never stop. To yield events in time order despite integration delays,
+/// events are held back until no later simulated event can be before them.
+pub struct SimTrace<'a> {
+    simulator: Simulator<'a>,
+    // simulated events held back, by time and then in simulated order
+    held: BTreeMap<(SimTime, usize), SimEvent>,
+    simulated: usize,
+    // the largest delay an event's time in the output trace can be before the
+    // time it is simulated at
+    max_delay: Duration,
+    done: bool,
+}
+
+impl<'a> Simulator<'a> {
+    /// Turn the simulator into an iterator over its output trace, see
+    /// [`SimTrace`].
+    pub fn into_trace(self) -> SimTrace<'a> {
+        // reported events have the reporting delay removed in the output trace
+        let max_delay = [self.args.client_integration, self.args.server_integration]
+            .into_iter()
+            .flatten()
+            .chain(self.args.relays.iter().filter_map(|r| r.integration))
+            .map(|i| i.reporting_delay.max())
+            .chain(
+                self.flows
+                    .iter()
+                    .flat_map(|f| f.hops.iter().map(|h| h.sq.max_delay())),
+            )
+            .fold(Duration::ZERO, Duration::max);
+        SimTrace {
+            simulator: self,
+            held: BTreeMap::new(),
+            simulated: 0,
+            max_delay,
+            done: false,
         }
+    }
+}
 
-        debug!("sim(): main loop end, more work?");
-        debug!("#########################################################");
+impl SimTrace<'_> {
+    /// The simulator, e.g., to inspect its state.
+    pub fn simulator(&self) -> &Simulator<'_> {
+        &self.simulator
     }
+}
 
-    // sort the trace by time
-    trace.sort_by(|a, b| a.time.cmp(&b.time));
+impl Iterator for SimTrace<'_> {
+    type Item = Result<SimEvent, SimError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // events yet to be simulated are at or after the current time
+            let earliest = self.simulator.current_time() - self.max_delay;
+            if let Some(held) = self.held.first_entry() {
+                if self.done || held.key().0 <= earliest {
+                    return Some(Ok(held.remove()));
+                }
+            }
+            if self.done {
+                return None;
+            }
 
-    trace
+            match self.simulator.step() {
+                Ok(Some(event)) => {
+                    self.held.insert((event.time, self.simulated), event);
+                    self.simulated += 1;
+                }
+                Ok(None) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+// the time of the event in the output trace, adjusted for any integration
+// delays: for packets, the time on the wire
+fn trace_time(next: &SimEvent) -> SimTime {
+    match next.event {
+        // padding adds the action delay
+        TriggerEvent::PaddingSent { .. } => next.time + next.delay,
+        // reported events remove the reporting delay
+        TriggerEvent::PaddingRecv { .. }
+        | TriggerEvent::NonPaddingRecv { .. }
+        | TriggerEvent::NonPaddingSent { .. } => next.time - next.delay,
+        _ => next.time,
+    }
 }
 
 fn pick_next<M: AsRef<[Machine]>>(
     sq: &mut SimQueue,
     client: &mut SimState<M>,
     server: &mut SimState<M>,
-    current_time: Instant,
+    current_time: SimTime,
 ) -> Option<SimEvent> {
     // find the earliest scheduled, blocked, and queued events to determine the
     // next event
@@ -506,7 +1298,7 @@ fn pick_next<M: AsRef<[Machine]>>(
         debug!("\tpick_next(): picked blocking");
         // create SimEvent and move blocking into (what soon will be) the past
         // to indicate that it has been processed
-        let time: Instant;
+        let time: SimTime;
         // ASSUMPTION: block outgoing is reported from integration
         let delay: Duration;
         let client_earliest =
@@ -531,9 +1323,14 @@ fn pick_next<M: AsRef<[Machine]>>(
             event: TriggerEvent::BlockingEnd,
             time,
             delay,
-            fuzz: fastrand::i32(..),
+            fuzz: rng::fuzz(),
             bypass: false,
             replace: false,
+            dropped: false,
+            input: false,
+            id: None,
+            flow: 0,
+            relay: None,
         });
     }
 
@@ -541,7 +1338,7 @@ fn pick_next<M: AsRef<[Machine]>>(
     // putting the event into the sim queue, and then recurse
     debug!("\tpick_next(): picked scheduled");
     let target = current_time + s;
-    let act = do_scheduled(client, server, current_time, target);
+    let act = do_scheduled(client, server, target);
     if let Some(a) = act {
         sq.push_sim(a.clone(), Reverse(a.time));
     }
@@ -551,42 +1348,31 @@ fn pick_next<M: AsRef<[Machine]>>(
 fn do_scheduled<M: AsRef<[Machine]>>(
     client: &mut SimState<M>,
     server: &mut SimState<M>,
-    current_time: Instant,
-    target: Instant,
+    target: SimTime,
 ) -> Option<SimEvent> {
-    // find the action
-    let mut a = ScheduledAction {
-        action: None,
-        time: current_time,
+    // find the action, at the client before the server, and of the machine
+    // with the lowest id if several are due at once: the maps of scheduled
+    // actions are not in any particular order, but the output must be
+    let due = |state: &SimState<M>| {
+        state
+            .scheduled_action
+            .iter()
+            .filter(|(_, sa)| sa.action.is_some() && sa.time == target)
+            .map(|(mi, _)| *mi)
+            .min_by_key(|mi| mi.into_raw())
     };
-    let mut a_is_client = false;
-    let mut a_is_found = false;
-
-    client.scheduled_action.retain(|&_mi, sa| {
-        if !a_is_found && sa.action.is_some() && sa.time == target {
-            a = sa.clone();
-            a_is_client = true;
-            a_is_found = true;
-            return false;
-        };
-        true
-    });
-
-    // cannot schedule a None action, so if we found one, done
-    if a.action.is_none() {
-        server.scheduled_action.retain(|&_mi, sa| {
-            if !a_is_found && sa.action.is_some() && sa.time == target {
-                a = sa.clone();
-                a_is_client = false;
-                a_is_found = true;
-                return false;
-            };
-            true
-        });
-    }
-
-    // no action found
-    assert!(a_is_found, "BUG: no action found");
+    let (a_is_client, mi) = match (due(client), due(server)) {
+        (Some(mi), _) => (true, mi),
+        (None, Some(mi)) => (false, mi),
+        // no action found
+        (None, None) => panic!("BUG: no action found"),
+    };
+    let state = if a_is_client {
+        &mut *client
+    } else {
+        &mut *server
+    };
+    let a = state.scheduled_action.remove(&mi)?;
 
     // do the action
     match a.action? {
@@ -615,9 +1401,14 @@ fn do_scheduled<M: AsRef<[Machine]>>(
                 time: a.time,
                 delay: action_delay,
                 client: a_is_client,
+                dropped: false,
                 bypass,
                 replace,
-                fuzz: fastrand::i32(..),
+                input: false,
+                id: None,
+                flow: 0,
+                relay: None,
+                fuzz: rng::fuzz(),
             })
         }
         Action::BlockOutgoing {
@@ -658,9 +1449,14 @@ fn do_scheduled<M: AsRef<[Machine]>>(
                 time: reported,
                 delay: total_delay,
                 client: a_is_client,
+                dropped: false,
                 bypass: event_bypass,
                 replace: false,
-                fuzz: fastrand::i32(..),
+                input: false,
+                id: None,
+                flow: 0,
+                relay: None,
+                fuzz: rng::fuzz(),
             })
         }
     }
@@ -669,14 +1465,15 @@ fn do_scheduled<M: AsRef<[Machine]>>(
 fn trigger_update<M: AsRef<[Machine]>>(
     state: &mut SimState<M>,
     next: &SimEvent,
-    current_time: &Instant,
+    current_time: &SimTime,
 ) {
     let trigger_delay = state.trigger_delay();
 
     // parse actions and update
+    let now = state.instant(*current_time);
     for action in state
         .framework
-        .trigger_events(&[next.event.clone()], *current_time)
+        .trigger_events(std::slice::from_ref(&next.event), now)
     {
         match action {
             Action::Cancel { machine } => {
@@ -721,83 +1518,3 @@ fn trigger_update<M: AsRef<[Machine]>>(
         };
     }
 }
-
-/// Parse a trace into a [`SimQueue`] for use with [`sim`].
-///
-/// The trace should contain one or more lines of the form
-/// "time,direction,size\n", where time is in nanoseconds relative to the first
-/// line, direction is either "s" for sent or "r" for received, and size is the
-/// number of bytes sent or received. The delay is used to model the network
-/// delay between the client and server. Returns a SimQueue with the events in
-/// the trace for use with [`sim`].
-
-pub fn parse_trace(trace: &str, network: &Network) -> SimQueue {
-    parse_trace_advanced(trace, network, None, None)
-}
-
-pub fn parse_trace_advanced(
-    trace: &str,
-    network: &Network,
-    client: Option<&Integration>,
-    server: Option<&Integration>,
-) -> SimQueue {
-    let mut sq = SimQueue::new();
-
-    // we just need a random starting time to make sure that we don't start from
-    // absolute 0
-    let starting_time = Instant::now();
-
-    for l in trace.lines() {
-        let parts: Vec<&str> = l.split(',').collect();
-        if parts.len() == 3 {
-            let timestamp =
-                starting_time + Duration::from_nanos(parts[0].trim().parse::<u64>().unwrap());
-            let size = parts[2].trim().parse::<u64>().unwrap();
-
-            match parts[1] {
-                "s" | "sn" => {
-                    // client sent at the given time
-                    let reporting_delay = client
-                        .map(|i| i.reporting_delay.sample())
-                        .unwrap_or(Duration::from_micros(0));
-                    let reported = timestamp + reporting_delay;
-                    sq.push(
-                        TriggerEvent::NonPaddingSent {
-                            bytes_sent: size as u16,
-                        },
-                        true,
-                        reported,
-                        reporting_delay,
-                        Reverse(reported),
-                    );
-                }
-                "r" | "rn" => {
-                    // sent by server delay time ago
-                    let sent = timestamp.checked_sub(network.delay).unwrap();
-                    // but reported to the Maybenot framework at the server with delay
-                    let reporting_delay = server
-                        .map(|i| i.reporting_delay.sample())
-                        .unwrap_or(Duration::from_micros(0));
-                    let reported = sent + reporting_delay;
-                    sq.push(
-                        TriggerEvent::NonPaddingSent {
-                            bytes_sent: size as u16,
-                        },
-                        false,
-                        reported,
-                        reporting_delay,
-                        Reverse(reported),
-                    );
-                }
-                "sp" | "rp" => {
-                    // TODO: figure out of ignoring is the right thing to do
-                }
-                _ => {
-                    panic!("invalid direction")
-                }
-            }
-        }
-    }
-
-    sq
-}
@@ -3,36 +3,695 @@
 
 use std::{
     cmp::{max, Reverse},
-    time::{Duration, Instant},
+    collections::VecDeque,
+    error::Error,
+    fmt::Debug,
+    time::Duration,
 };
 
 use log::debug;
 use maybenot::{event::Event, framework::TriggerEvent, machine::Machine};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand_distr::{Distribution, LogNormal, Normal, Pareto};
 
-use crate::{queue::SimQueue, SimEvent, SimState};
+use crate::{
+    integration::BinDist, queue::SimQueue, rng, time::SimTime, SimEvent, SimState, SimulatorArgs,
+};
+
+/// A distribution of network delays, used to model jitter. All parameters are
+/// in milliseconds, like the bins of a [`BinDist`]. Samples are clamped to be
+/// non-negative.
+#[derive(Debug, Clone)]
+pub enum DelayDist {
+    /// A distribution of delays in bins, see [`BinDist`].
+    Bins(BinDist),
+    /// Normal distribution with set mean and standard deviation.
+    Normal { mean: f64, std_dev: f64 },
+    /// LogNormal distribution with set mu and sigma (of the underlying normal
+    /// distribution).
+    LogNormal { mu: f64, sigma: f64 },
+    /// Pareto distribution with set scale and shape.
+    Pareto { scale: f64, shape: f64 },
+}
+
+impl DelayDist {
+    pub fn sample(&self) -> Duration {
+        let ms = match self {
+            DelayDist::Bins(bins) => return bins.sample(),
+            DelayDist::Normal { mean, std_dev } => Normal::new(*mean, *std_dev)
+                .map(|d| rng::with_rng(|rng| d.sample(rng)))
+                .unwrap_or(*mean),
+            DelayDist::LogNormal { mu, sigma } => LogNormal::new(*mu, *sigma)
+                .map(|d| rng::with_rng(|rng| d.sample(rng)))
+                .unwrap_or(0.0),
+            DelayDist::Pareto { scale, shape } => Pareto::new(*scale, *shape)
+                .map(|d| rng::with_rng(|rng| d.sample(rng)))
+                .unwrap_or(*scale),
+        };
+        // milliseconds to microseconds, like BinDist
+        Duration::from_micros((ms.max(0.0) * 1000.0) as u64)
+    }
+}
+
+/// A packet sent over the network, as given to a [`NetworkModel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkPacket {
+    /// True if the packet was sent by the client, false if by the server.
+    pub client: bool,
+    /// The size of the packet in bytes.
+    pub size: u16,
+    /// The time the packet was sent.
+    pub sent: SimTime,
+    /// True if the packet is padding.
+    pub padding: bool,
+}
+
+/// A model of the network between the client and server, deciding when (and
+/// if) each sent packet is delivered to the other side. Implemented by
+/// [`Network`]; implement it to simulate any other channel.
+pub trait NetworkModel: Debug {
+    /// Returns a copy of the model for a simulation run. Models typically keep
+    /// state (e.g., packets queued on a link) during a run, which must not
+    /// carry over between runs: the simulator never modifies the model it is
+    /// given, only the copy.
+    fn new_run(&self) -> Box<dyn NetworkModel>;
+
+    /// Returns the time the packet is delivered to the other side, or None if
+    /// the packet is dropped. Called in the order the simulator processes
+    /// packets, which with integration delays is not necessarily the order
+    /// they are sent: a packet may be sent before packets already delivered.
+    fn deliver(&mut self, packet: &NetworkPacket) -> Option<SimTime>;
 
-/// A model of the network between the client and server. TODO: make this more
-/// than just a delay.
+    /// The typical one-way delay of a packet of the given size sent by the
+    /// client (if client is true) or the server, at the given time relative to
+    /// the start of the trace. Used when parsing traces to determine when the
+    /// server sent packets received by the client.
+    fn nominal_delay(&self, client: bool, time: Duration, size: u16) -> Duration;
+
+    /// The retransmission timeout for lost non-padding packets sent by the
+    /// client (if client is true) or the server. None if lost packets are not
+    /// retransmitted.
+    fn rto(&self, _client: bool) -> Option<Duration> {
+        None
+    }
+}
+
+/// A model of the network between the client and server, made up of one
+/// [`Link`] in each direction. The links can be different, e.g., to model
+/// asymmetric consumer connections.
 #[derive(Debug, Clone)]
 pub struct Network {
-    pub delay: Duration,
+    /// The link for packets sent by the client to the server.
+    pub uplink: Link,
+    /// The link for packets sent by the server to the client.
+    pub downlink: Link,
 }
 
 impl Network {
+    /// A symmetric network with the same delay in both directions.
     pub fn new(delay: Duration) -> Self {
-        Self { delay }
+        Self {
+            uplink: Link::new(delay),
+            downlink: Link::new(delay),
+        }
     }
 
-    pub fn sample(&self) -> Duration {
-        self.delay
+    /// An asymmetric network with different uplink (client to server) and
+    /// downlink (server to client).
+    pub fn asymmetric(uplink: Link, downlink: Link) -> Self {
+        Self { uplink, downlink }
+    }
+
+    /// The link used by packets sent by the client (if client is true) or the
+    /// server.
+    pub fn link(&self, client: bool) -> &Link {
+        if client {
+            &self.uplink
+        } else {
+            &self.downlink
+        }
+    }
+
+    /// Returns the time a packet of the given size, sent at the given time by
+    /// the client (if client is true) or the server, arrives at the other side,
+    /// or None if the packet is lost.
+    pub fn arrival(&mut self, sent: SimTime, size: u16, client: bool) -> Option<SimTime> {
+        if client {
+            self.uplink.arrival(sent, size)
+        } else {
+            self.downlink.arrival(sent, size)
+        }
+    }
+}
+
+impl NetworkModel for Network {
+    fn new_run(&self) -> Box<dyn NetworkModel> {
+        Box::new(self.clone())
+    }
+
+    fn deliver(&mut self, packet: &NetworkPacket) -> Option<SimTime> {
+        self.arrival(packet.sent, packet.size, packet.client)
+    }
+
+    fn nominal_delay(&self, client: bool, time: Duration, size: u16) -> Duration {
+        self.link(client).nominal_delay(time, size)
+    }
+
+    fn rto(&self, client: bool) -> Option<Duration> {
+        self.link(client).rto
+    }
+}
+
+/// A model of packet loss on a [`Link`].
+#[derive(Debug, Clone)]
+pub enum LossModel {
+    /// Each packet is lost independently with probability p.
+    Bernoulli { p: f64 },
+    /// The Gilbert-Elliott model of bursty loss: the link is either in a good
+    /// or a bad state, with a separate loss probability in each state. After
+    /// each packet, the link moves from the good to the bad state with
+    /// probability p, and from the bad to the good state with probability r.
+    GilbertElliott {
+        p: f64,
+        r: f64,
+        loss_good: f64,
+        loss_bad: f64,
+    },
+}
+
+/// A bottleneck buffer (e.g., a router queue) in front of a [`Link`], drained
+/// at the bandwidth of the link. Packets that do not fit are dropped according
+/// to the drop policy.
+#[derive(Debug, Clone)]
+pub struct Buffer {
+    /// The capacity of the buffer. Packets waiting to be transmitted and the
+    /// packet being transmitted count towards the capacity.
+    pub capacity: BufferCapacity,
+    /// The policy for dropping packets.
+    pub policy: DropPolicy,
+}
+
+/// The capacity of a [`Buffer`].
+#[derive(Debug, Clone, Copy)]
+pub enum BufferCapacity {
+    /// At most the given number of packets.
+    Packets(usize),
+    /// At most the given number of bytes.
+    Bytes(usize),
+}
+
+/// The policy for dropping packets in a [`Buffer`].
+#[derive(Debug, Clone, Copy)]
+pub enum DropPolicy {
+    /// Drop packets that arrive when the buffer is full.
+    TailDrop,
+    /// Random Early Detection (RED): an average of the buffer occupancy is
+    /// kept as an exponentially weighted moving average with the given weight.
+    /// Below min_threshold, no packets are dropped. Between min_threshold and
+    /// max_threshold, packets are dropped with a probability growing linearly
+    /// up to max_p. Above max_threshold, all packets are dropped. Thresholds
+    /// are fractions of the capacity. Packets that arrive when the buffer is
+    /// full are always dropped.
+    Red {
+        min_threshold: f64,
+        max_threshold: f64,
+        max_p: f64,
+        weight: f64,
+    },
+}
+
+/// The conditions of a [`Link`] from a point in time until the next sample in
+/// a [`LinkTrace`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkSample {
+    /// The time of the sample, relative to the start of the trace.
+    pub time: Duration,
+    /// The one-way delay of the link.
+    pub delay: Duration,
+    /// The bandwidth of the link in bits per second, None for infinite.
+    pub bandwidth: Option<u64>,
+}
+
+/// Network conditions that change over time, e.g., replayed from measurements
+/// of a real network. Used by a [`Link`] to look up the delay and bandwidth at
+/// any point in time during the simulation.
+#[derive(Debug, Clone)]
+pub struct LinkTrace {
+    /// samples sorted by time, the first at time zero
+    samples: Vec<LinkSample>,
+    /// If set, the conditions repeat with this period (e.g., the length of a
+    /// looping Mahimahi trace). Otherwise, the last sample holds forever.
+    pub period: Option<Duration>,
+}
+
+impl LinkTrace {
+    /// Creates a trace from samples. The samples are sorted by time, and the
+    /// conditions before the first sample are those of the first sample.
+    pub fn new(mut samples: Vec<LinkSample>) -> Result<Self, Box<dyn Error>> {
+        if samples.is_empty() {
+            return Err("a link trace needs at least one sample".into());
+        }
+        samples.sort_by_key(|s| s.time);
+        samples[0].time = Duration::ZERO;
+        Ok(Self {
+            samples,
+            period: None,
+        })
+    }
+
+    /// Parses a log of measured conditions, one sample per line of the form
+    /// "time,rtt,bandwidth\n". Time and RTT are in milliseconds (fractions
+    /// allowed) and bandwidth in bits per second. The one-way delay of the
+    /// link is half the RTT. Empty lines and lines starting with '#' are
+    /// ignored.
+    pub fn parse(log: &str) -> Result<Self, Box<dyn Error>> {
+        let mut samples = vec![];
+        for (i, l) in log.lines().enumerate() {
+            let l = l.trim();
+            if l.is_empty() || l.starts_with('#') {
+                continue;
+            }
+            let parts: Vec<&str> = l.split(',').map(str::trim).collect();
+            if parts.len() != 3 {
+                return Err(format!("line {}: expected time,rtt,bandwidth", i + 1).into());
+            }
+            let time: f64 = parts[0].parse()?;
+            let rtt: f64 = parts[1].parse()?;
+            let bandwidth: u64 = parts[2].parse()?;
+            let (Ok(time), Ok(delay)) = (
+                Duration::try_from_secs_f64(time / 1000.0),
+                Duration::try_from_secs_f64(rtt / 2000.0),
+            ) else {
+                return Err(format!("line {}: negative or non-finite time or rtt", i + 1).into());
+            };
+            samples.push(LinkSample {
+                time,
+                delay,
+                bandwidth: Some(bandwidth),
+            });
+        }
+        Self::new(samples)
+    }
+
+    /// Parses a Mahimahi packet delivery trace: each line is a timestamp in
+    /// milliseconds at which one MTU-sized (1500 byte) packet can be delivered.
+    /// The delivery opportunities are turned into a bandwidth for each window
+    /// of the given length. Like in Mahimahi, the trace repeats once it ends.
+    /// Mahimahi traces carry no delay, so the delay is given.
+    pub fn from_mahimahi(
+        trace: &str,
+        window: Duration,
+        delay: Duration,
+    ) -> Result<Self, Box<dyn Error>> {
+        if window.is_zero() {
+            return Err("the window must be positive".into());
+        }
+        let mut timestamps = vec![];
+        for (i, l) in trace.lines().enumerate() {
+            let l = l.trim();
+            if l.is_empty() {
+                continue;
+            }
+            let ms: u64 = l
+                .parse()
+                .map_err(|e| format!("line {}: invalid timestamp: {}", i + 1, e))?;
+            timestamps.push(Duration::from_millis(ms));
+        }
+        let end = timestamps.iter().max().copied().unwrap_or_default();
+        if end.is_zero() {
+            return Err("the Mahimahi trace must end after time zero".into());
+        }
+
+        // count the delivery opportunities in each window: the trace repeats
+        // at its last timestamp, so that timestamp is time zero of the next
+        // repetition
+        let windows = end.as_nanos().div_ceil(window.as_nanos()) as usize;
+        let mut opportunities = vec![0u64; windows];
+        for t in timestamps {
+            let t = t.as_nanos() % end.as_nanos();
+            opportunities[(t / window.as_nanos()) as usize] += 1;
+        }
+        let samples = opportunities
+            .into_iter()
+            .enumerate()
+            .map(|(i, n)| LinkSample {
+                time: window * i as u32,
+                delay,
+                bandwidth: Some((n * 1500 * 8 * 1_000_000_000) / window.as_nanos() as u64),
+            })
+            .collect();
+
+        let mut trace = Self::new(samples)?;
+        trace.period = Some(end);
+        Ok(trace)
+    }
+
+    /// The samples of the trace, sorted by time.
+    pub fn samples(&self) -> &[LinkSample] {
+        &self.samples
+    }
+
+    /// Returns the conditions at the given time (relative to the start of the
+    /// trace) and when they end, if they ever do.
+    pub fn at(&self, time: Duration) -> (&LinkSample, Option<Duration>) {
+        let (base, local) = match self.period {
+            Some(period) if !period.is_zero() => {
+                let local = Duration::from_nanos((time.as_nanos() % period.as_nanos()) as u64);
+                (time - local, local)
+            }
+            _ => (Duration::ZERO, time),
+        };
+        let i = self.samples.partition_point(|s| s.time <= local) - 1;
+        let end = match self.samples.get(i + 1) {
+            Some(next) => Some(base + next.time),
+            None => self.period.map(|period| base + period),
+        };
+        (&self.samples[i], end)
+    }
+
+    /// Returns the time (relative to the start of the trace) when a
+    /// packet of the given size, starting transmission at the given time, is
+    /// done being transmitted. None if there is never enough bandwidth.
+    pub fn transmitted(&self, start: Duration, size: u16) -> Option<Duration> {
+        let mut remaining = size as f64 * 8.0;
+        let mut time = start;
+        // segments in a row without any bandwidth, to detect a trace that
+        // never delivers anything
+        let mut stalled = 0;
+        loop {
+            let (sample, end) = self.at(time);
+            let bps = match sample.bandwidth {
+                None => return Some(time),
+                Some(bps) => bps as f64,
+            };
+            if bps > 0.0 {
+                stalled = 0;
+                let needed = Duration::from_secs_f64(remaining / bps);
+                match end {
+                    Some(end) if time + needed > end => {
+                        remaining -= (end - time).as_secs_f64() * bps;
+                        time = end;
+                    }
+                    _ => return Some(time + needed),
+                }
+            } else {
+                stalled += 1;
+                match end {
+                    Some(end) if stalled <= self.samples.len() => time = end,
+                    _ => return None,
+                }
+            }
+        }
     }
 }
 
-/// The network replace window is the time window in which we can replace
-/// padding with existing padding or non-padding already queued (or about to be
-/// queued up). The behavior here is tricky, since it'll differ how different
-/// implementations handle it.
-const NETWORK_REPLACE_WINDOW: Duration = Duration::from_micros(1);
+// how long before the last packet sent a link remembers packets, to queue up
+// packets given out of order behind the packets sent before them
+const SENT_HISTORY: Duration = Duration::from_secs(1);
+
+// a packet sent on a link
+#[derive(Debug, Clone)]
+struct SentPacket {
+    sent: SimTime,
+    size: u16,
+    // when the packet is done transmitting
+    done: SimTime,
+    // when the link is done transmitting this and all packets sent before
+    // it, and the last arrival of them (if any arrived)
+    busy_until: SimTime,
+    last_arrival: Option<SimTime>,
+}
+
+/// A model of one direction of the network: a delay with optional jitter, an
+/// optional bandwidth limit with a bottleneck buffer, and optional packet loss.
+#[derive(Debug, Clone)]
+pub struct Link {
+    /// The one-way (propagation) delay of the link. This is the fixed part of
+    /// the delay, the minimum delay if there is jitter.
+    pub delay: Duration,
+    /// Random jitter added to the delay of each packet, if any. Set the delay
+    /// to zero to have the distribution make up the entire delay.
+    pub jitter: Option<DelayDist>,
+    /// Whether jitter may reorder packets. If false (the default), packets are
+    /// always delivered in the order they were sent.
+    pub reorder: bool,
+    /// The bandwidth of the link in bits per second, if any. With a bandwidth,
+    /// each packet takes time to be serialized onto the link and packets queue
+    /// up behind each other. None means infinite bandwidth.
+    pub bandwidth: Option<u64>,
+    /// Conditions that change over time, if any. When set, the delay and
+    /// bandwidth of the link follow the trace instead of the fields above.
+    pub trace: Option<LinkTrace>,
+    /// The bottleneck buffer in front of the link, if any. Without a buffer,
+    /// there is no limit on the number of packets queued up. The buffer only
+    /// fills up if the bandwidth is limited.
+    pub buffer: Option<Buffer>,
+    /// The packet loss model of the link, if any. Lost packets are sent but
+    /// never received, and are marked as dropped in the simulated trace.
+    pub loss: Option<LossModel>,
+    /// The seed for sampling packet loss, including random early drops in the
+    /// buffer. If None, the seed is taken from the RNG of the simulator, see
+    /// [`rng`].
+    pub loss_seed: Option<u64>,
+    /// The retransmission timeout (RTO) for lost non-padding packets. If set,
+    /// the sender sends a lost non-padding packet again after the RTO. Lost
+    /// padding is never retransmitted.
+    pub rto: Option<Duration>,
+    /// the packets recently sent on the link, in the order they were sent
+    sent: VecDeque<SentPacket>,
+    /// the average buffer occupancy (fraction of capacity) for RED
+    queued_avg: f64,
+    /// the rng for packet loss, created on first use from loss_seed
+    loss_rng: Option<StdRng>,
+    /// whether the Gilbert-Elliott loss model is in the bad state
+    loss_bad_state: bool,
+}
+
+impl Link {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            jitter: None,
+            reorder: false,
+            bandwidth: None,
+            trace: None,
+            buffer: None,
+            loss: None,
+            loss_seed: None,
+            rto: None,
+            sent: VecDeque::new(),
+            queued_avg: 0.0,
+            loss_rng: None,
+            loss_bad_state: false,
+        }
+    }
+
+    /// Samples the delay of a packet: the fixed delay plus any jitter.
+    pub fn sample(&self) -> Duration {
+        match &self.jitter {
+            Some(jitter) => self.delay + jitter.sample(),
+            None => self.delay,
+        }
+    }
+
+    /// The delay of a packet of the given size sent on an idle link, at the
+    /// given time relative to the start of the trace, without jitter.
+    pub fn nominal_delay(&self, time: Duration, size: u16) -> Duration {
+        match &self.trace {
+            Some(trace) => {
+                let (sample, _) = trace.at(time);
+                let transmitted = trace.transmitted(time, size).unwrap_or(time);
+                sample.delay + (transmitted - time)
+            }
+            None => self.delay + self.transmission_time(size),
+        }
+    }
+
+    // time relative to the start of the trace, for replaying a LinkTrace
+    fn offset(&self, time: SimTime) -> Duration {
+        time.saturating_duration_since(SimTime::ZERO)
+    }
+
+    /// The time it takes to serialize a packet of the given size onto the
+    /// link. Zero without a bandwidth limit.
+    pub fn transmission_time(&self, size: u16) -> Duration {
+        match self.bandwidth {
+            Some(bps) if bps > 0 => {
+                Duration::from_nanos((size as u64 * 8 * 1_000_000_000).div_ceil(bps))
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Returns the time a packet of the given size, sent at the given time,
+    /// arrives at the other end of the link, or None if the packet is lost or
+    /// dropped by the buffer. The packet has to wait for any packets sent
+    /// before it to be transmitted before it is sent itself, and (unless
+    /// reordering is allowed) cannot arrive before any packet sent before it.
+    ///
+    /// Packets may be given out of the order they are sent, e.g., when they
+    /// are reported late because of integration delays. A packet sent before
+    /// packets already given only queues up behind the packets sent before
+    /// it, up to a second before the last packet sent.
+    pub fn arrival(&mut self, sent: SimTime, size: u16) -> Option<SimTime> {
+        // the packets sent before this one, the last of which knows when the
+        // link is done with them all
+        let i = self.sent.partition_point(|p| p.sent <= sent);
+        let before = i.checked_sub(1).map(|j| self.sent[j].clone());
+        if self.is_dropped(i, sent, size) {
+            debug!("\tdropped by buffer");
+            return None;
+        }
+
+        let start = match &before {
+            Some(before) => max(before.busy_until, sent),
+            None => sent,
+        };
+        let done = match &self.trace {
+            Some(trace) => {
+                let offset = self.offset(start);
+                let Some(transmitted) = trace.transmitted(offset, size) else {
+                    debug!("\tno bandwidth left in link trace");
+                    return None;
+                };
+                start + (transmitted - offset)
+            }
+            None => start + self.transmission_time(size),
+        };
+        // a lost packet still took time to transmit on the link
+        let arrival = if self.is_lost() {
+            debug!("\tlost on link");
+            None
+        } else {
+            let arrival = match &self.trace {
+                Some(trace) => {
+                    let jitter = self.jitter.as_ref().map(|j| j.sample()).unwrap_or_default();
+                    done + trace.at(self.offset(done)).0.delay + jitter
+                }
+                None => done + self.sample(),
+            };
+            match before.as_ref().and_then(|b| b.last_arrival) {
+                Some(last) if !self.reorder => Some(max(arrival, last)),
+                _ => Some(arrival),
+            }
+        };
+
+        self.sent.insert(
+            i,
+            SentPacket {
+                sent,
+                size,
+                done,
+                busy_until: before.as_ref().map_or(done, |b| max(b.busy_until, done)),
+                last_arrival: max(before.and_then(|b| b.last_arrival), arrival),
+            },
+        );
+        // packets sent after this one (if given out of order) now also have
+        // to queue up and arrive behind it
+        for later in self.sent.range_mut(i + 1..) {
+            later.busy_until = max(later.busy_until, done);
+            later.last_arrival = max(later.last_arrival, arrival);
+        }
+        // forget packets sent long before the last one, keeping one for the
+        // packets sent after it
+        let last = self.sent.back().map(|p| p.sent).unwrap_or(sent);
+        while self.sent.len() > 1 && self.sent[1].sent + SENT_HISTORY < last {
+            self.sent.pop_front();
+        }
+        arrival
+    }
+
+    /// Checks if a packet of the given size arriving at the buffer at the
+    /// given time is dropped, behind the first n packets sent still in the
+    /// buffer by then.
+    fn is_dropped(&mut self, n: usize, now: SimTime, size: u16) -> bool {
+        let Some(buffer) = self.buffer.clone() else {
+            return false;
+        };
+        // the link is done with all packets sent before one it is done with
+        let queued: Vec<u16> = self
+            .sent
+            .range(..n)
+            .rev()
+            .take_while(|p| p.busy_until > now)
+            .filter(|p| p.done > now)
+            .map(|p| p.size)
+            .collect();
+
+        // the occupancy of the buffer as a fraction of its capacity, and if
+        // the packet fits at all
+        let (occupancy, fits) = match buffer.capacity {
+            BufferCapacity::Packets(max) => (queued.len() as f64 / max as f64, queued.len() < max),
+            BufferCapacity::Bytes(max) => {
+                let bytes: usize = queued.iter().map(|s| *s as usize).sum();
+                (bytes as f64 / max as f64, bytes + size as usize <= max)
+            }
+        };
+        if !fits {
+            return true;
+        }
+
+        match buffer.policy {
+            DropPolicy::TailDrop => false,
+            DropPolicy::Red {
+                min_threshold,
+                max_threshold,
+                max_p,
+                weight,
+            } => {
+                self.queued_avg = (1.0 - weight) * self.queued_avg + weight * occupancy;
+                if self.queued_avg < min_threshold {
+                    false
+                } else if self.queued_avg >= max_threshold {
+                    true
+                } else {
+                    let p =
+                        max_p * (self.queued_avg - min_threshold) / (max_threshold - min_threshold);
+                    self.rng().gen_bool(p.clamp(0.0, 1.0))
+                }
+            }
+        }
+    }
+
+    fn rng(&mut self) -> &mut StdRng {
+        let seed = self.loss_seed;
+        self.loss_rng.get_or_insert_with(|| match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => rng::with_rng(|rng| StdRng::seed_from_u64(rng.gen())),
+        })
+    }
+
+    /// Samples if the next packet is lost, moving the loss model forward.
+    fn is_lost(&mut self) -> bool {
+        let Some(loss) = self.loss.clone() else {
+            return false;
+        };
+        let bad_state = self.loss_bad_state;
+        let rng = self.rng();
+
+        match loss {
+            LossModel::Bernoulli { p } => rng.gen_bool(p.clamp(0.0, 1.0)),
+            LossModel::GilbertElliott {
+                p,
+                r,
+                loss_good,
+                loss_bad,
+            } => {
+                let lost = if bad_state {
+                    rng.gen_bool(loss_bad.clamp(0.0, 1.0))
+                } else {
+                    rng.gen_bool(loss_good.clamp(0.0, 1.0))
+                };
+                self.loss_bad_state = if bad_state {
+                    !rng.gen_bool(r.clamp(0.0, 1.0))
+                } else {
+                    rng.gen_bool(p.clamp(0.0, 1.0))
+                };
+                lost
+            }
+        }
+    }
+}
 
 // For (non-)padding sent, queue the corresponding padding recv event: in other
 // words, where we simulate sending packets. The block below is actually the
@@ -40,12 +699,13 @@ const NETWORK_REPLACE_WINDOW: Duration = Duration::from_micros(1);
 // client and the server. Returns true if a (non-)padding packet was sent or
 // received (i.e., there was network activity), false otherwise.
 pub fn sim_network_activity<M: AsRef<[Machine]>>(
-    next: &SimEvent,
+    next: &mut SimEvent,
     sq: &mut SimQueue,
     state: &SimState<M>,
     recipient: &SimState<M>,
-    network: &Network,
-    current_time: &Instant,
+    network: &mut dyn NetworkModel,
+    current_time: &SimTime,
+    args: &SimulatorArgs,
 ) -> bool {
     let side = if next.client { "client" } else { "server" }.to_string();
 
@@ -64,18 +724,36 @@ pub fn sim_network_activity<M: AsRef<[Machine]>>(
             // simulator (support for integration delays was bolted on late),
             // because it would move time backwards. Therefore, we clamp.
             let reporting_delay = recipient.reporting_delay();
-            let reported = max(
-                next.time - next.delay + network.sample() + reporting_delay,
-                *current_time,
-            );
-            sq.push(
+            let Some(arrival) = network.deliver(&NetworkPacket {
+                client: next.client,
+                size: bytes_sent,
+                sent: next.time - next.delay,
+                padding: false,
+            }) else {
+                debug!("\tlost non-padding sent @{}", side);
+                next.dropped = true;
+                // the sender sends the packet again after the RTO, if any
+                if let Some(rto) = network.rto(next.client) {
+                    let retransmit = next.time + rto;
+                    sq.push_with_id(
+                        TriggerEvent::NonPaddingSent { bytes_sent },
+                        next.client,
+                        retransmit,
+                        next.delay,
+                        next.id,
+                    );
+                }
+                return true;
+            };
+            let reported = max(arrival + reporting_delay, *current_time);
+            sq.push_with_id(
                 TriggerEvent::NonPaddingRecv {
                     bytes_recv: bytes_sent,
                 },
                 !next.client,
                 reported,
                 reporting_delay,
-                Reverse(reported),
+                next.id,
             );
 
             true
@@ -85,24 +763,25 @@ pub fn sim_network_activity<M: AsRef<[Machine]>>(
                 // This is where it gets tricky: we MAY replace the padding with
                 // existing padding or non-padding already queued (or about to
                 // be queued up). The behavior here is tricky, since it'll
-                // differ how different implementations handle it. When
-                // replacing, we allow bytes of equal or less size, without
-                // adjusting/compensating for the size difference. This is to
-                // allow easy constant-rate defenses accounting for
-                // variable-size packets. Note that replacing is the same as
-                // skipping to queue the padding recv event below.
+                // differ how different implementations handle it, so the
+                // decision is made by the replace policy. Note that replacing
+                // is the same as skipping to queue the padding recv event
+                // below.
 
                 // check if we can replace with last sent up to the network
                 // replace window: this probably poorly simulates an egress
-                // queue where it takes up to 1us to send the packet
+                // queue where it takes up to the window to send the packet
                 debug!(
                     "\treplace with earlier? {:?} <= {:?}",
                     next.time.duration_since(state.last_sent_time),
-                    NETWORK_REPLACE_WINDOW
+                    args.replace_window
                 );
-                if next.time.duration_since(state.last_sent_time) <= NETWORK_REPLACE_WINDOW
-                    && state.last_sent_size <= bytes_sent
-                {
+                if args.replace_policy.replace_with_last_sent(
+                    bytes_sent,
+                    state.last_sent_size,
+                    next.time.duration_since(state.last_sent_time),
+                    args.replace_window,
+                ) {
                     debug!("replacing padding sent with last sent @{}", side);
                     return false;
                 }
@@ -117,21 +796,27 @@ pub fn sim_network_activity<M: AsRef<[Machine]>>(
                     debug!(
                         "\treplace with queued? {:?} <= {:?}",
                         queued.time.duration_since(next.time),
-                        NETWORK_REPLACE_WINDOW
+                        args.replace_window
                     );
-                    if queued.client == next.client
-                        && queued.time.duration_since(next.time) <= NETWORK_REPLACE_WINDOW
-                    {
+                    if queued.client == next.client {
                         if let TriggerEvent::NonPaddingSent {
                             bytes_sent: queued_bytes_sent,
                         } = queued.event
                         {
-                            if queued_bytes_sent <= bytes_sent {
+                            if let Some(replaced_size) = args.replace_policy.replace_with_queued(
+                                bytes_sent,
+                                queued_bytes_sent,
+                                queued.time.duration_since(next.time),
+                                args.replace_window,
+                            ) {
                                 debug!("replacing padding sent with queued non-padding @{}", side,);
                                 // let the NonPaddingSent event bypass
                                 // blocking by making a copy of the event
                                 // with the appropriate flags set
                                 let mut tmp = queued.clone();
+                                tmp.event = TriggerEvent::NonPaddingSent {
+                                    bytes_sent: replaced_size,
+                                };
                                 tmp.bypass = true;
                                 tmp.replace = false;
                                 // we send the NonPadding now since it is queued
@@ -152,7 +837,18 @@ pub fn sim_network_activity<M: AsRef<[Machine]>>(
             debug!("\tqueue {}", Event::PaddingRecv);
             let reporting_delay = recipient.reporting_delay();
             // action delay + network + recipient reporting delay
-            let reported = next.time + next.delay + network.sample() + reporting_delay;
+            let Some(arrival) = network.deliver(&NetworkPacket {
+                client: next.client,
+                size: bytes_sent,
+                sent: next.time + next.delay,
+                padding: true,
+            }) else {
+                // lost padding is never retransmitted
+                debug!("\tlost padding sent @{}", side);
+                next.dropped = true;
+                return true;
+            };
+            let reported = arrival + reporting_delay;
             sq.push(
                 TriggerEvent::PaddingRecv {
                     bytes_recv: bytes_sent,
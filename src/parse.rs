@@ -0,0 +1,371 @@
+//! Parsing traces into a [`SimQueue`] for the simulator.
+
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    error::Error,
+    fmt,
+    io::{self, BufRead},
+    time::Duration,
+};
+
+use maybenot::framework::{MachineId, TriggerEvent};
+use rand::rngs::StdRng;
+
+use crate::{
+    integration::Integration,
+    network::NetworkModel,
+    queue::{new_event, SimQueue},
+    rng,
+    time::SimTime,
+};
+
+/// An error from parsing a trace, with the (1-indexed) line it occurred on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub kind: ParseErrorKind,
+}
+
+/// The reason a line of a trace could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// The line does not have three or four comma-separated fields.
+    InvalidFormat(String),
+    /// The timestamp is not a non-negative integer (nanoseconds).
+    InvalidTimestamp(String),
+    /// The size is not an integer that fits in a u16 (bytes).
+    InvalidSize(String),
+    /// The direction is not one of "s", "sn", "r", "rn", "sp", or "rp".
+    InvalidDirection(String),
+    /// The flow is not a non-negative integer.
+    InvalidFlow(String),
+    /// A received packet would have been sent by the server too long before
+    /// the start of the trace to be represented as a
+    /// [`SimTime`], given the nominal delay of the
+    /// network model.
+    ReceivedTooEarly,
+    /// The line could not be read, e.g., because it is not valid UTF-8.
+    Io(io::ErrorKind),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::InvalidFormat(l) => {
+                write!(f, "expected \"time,direction,size[,flow]\", got \"{}\"", l)
+            }
+            ParseErrorKind::InvalidTimestamp(t) => write!(f, "invalid timestamp \"{}\"", t),
+            ParseErrorKind::InvalidSize(s) => write!(f, "invalid size \"{}\"", s),
+            ParseErrorKind::InvalidDirection(d) => write!(f, "invalid direction \"{}\"", d),
+            ParseErrorKind::InvalidFlow(c) => write!(f, "invalid flow \"{}\"", c),
+            ParseErrorKind::ReceivedTooEarly => {
+                write!(f, "received before it could have been sent by the server")
+            }
+            ParseErrorKind::Io(kind) => write!(f, "failed to read: {}", kind),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// Parse a trace into a [`SimQueue`] for use with [`sim`](crate::sim).
+///
+/// The trace should contain one or more lines of the form
+/// "time,direction,size\n", where time is in nanoseconds relative to the first
+/// line, direction is either "s" for sent or "r" for received, and size is the
+/// number of bytes sent or received. The nominal delay of the network model
+/// from the server to the client (e.g., the delay and transmission time of the
+/// downlink of a [`Network`](crate::network::Network)) is used to model when
+/// the server sent packets received by the client. Returns a SimQueue with the
+/// events in the trace for use with [`sim`](crate::sim), or an error for the
+/// first line that could not be parsed. Empty lines are ignored.
+///
+/// A line may have a fourth field, "time,direction,size,flow", with the flow
+/// (connection, e.g., a tunnel or circuit) of the packet as an integer: each
+/// flow is simulated with its own machines, see
+/// [`Simulator`](crate::Simulator). Lines without a flow are in flow 0.
+///
+/// Events are given random fuzz (and, with integrations, random reporting
+/// delays): set [`ParseArgs::seed`] with [`parse_trace_advanced`] for a
+/// reproducible queue, see [`rng`].
+pub fn parse_trace(trace: &str, network: &dyn NetworkModel) -> Result<SimQueue, ParseError> {
+    parse_trace_advanced(trace, &ParseArgs::new(network))
+}
+
+/// Arguments for [`parse_trace_advanced`].
+#[derive(Clone, Debug)]
+pub struct ParseArgs<'a> {
+    /// The model of the network between the client and server, as used with
+    /// [`sim_advanced`](crate::sim_advanced).
+    pub network: &'a dyn NetworkModel,
+    pub client_integration: Option<&'a Integration>,
+    pub server_integration: Option<&'a Integration>,
+    /// If true, "sp" and "rp" lines are parsed as padding already in the trace
+    /// (e.g., from a previously defended trace), otherwise they are ignored.
+    /// The padding is sent over the network like any other packet and is
+    /// reported as padding in the simulated trace. Sending it is not reported
+    /// to any machine, but it counts toward the global padding limits of the
+    /// framework; receiving it is reported like any other padding.
+    pub padding: bool,
+    /// If true, the trace is replayed causally: non-padding sent by the client
+    /// is sent relative to when the client received the last non-padding
+    /// before it, and non-padding sent by the server relative to when the
+    /// server received the last non-padding before it (at the nominal delay of
+    /// the network model). Delays from, e.g., blocking then shift the rest of
+    /// the trace. Non-padding sent before anything was received, and padding,
+    /// is sent at its time in the trace. Non-padding sent in response to a
+    /// packet lost on the network (and not sent again, see
+    /// [`Link::rto`](crate::network::Link::rto)) is sent relative to when the
+    /// packet would have been received, at the nominal delay of the network.
+    pub causal: bool,
+    /// If true, the flow of each line is ignored and all packets are in flow
+    /// 0, e.g., to simulate a defense running once for all connections of a
+    /// host instead of once per connection.
+    pub single_flow: bool,
+    /// If set, seeds the RNG sampled while parsing, see [`rng`].
+    pub seed: Option<u64>,
+}
+
+impl<'a> ParseArgs<'a> {
+    pub fn new(network: &'a dyn NetworkModel) -> Self {
+        Self {
+            network,
+            client_integration: None,
+            server_integration: None,
+            padding: false,
+            causal: false,
+            single_flow: false,
+            seed: None,
+        }
+    }
+}
+
+// the machine of padding already in a trace: no machine in the framework, so
+// it only counts toward the global padding limits
+const TRACE_PADDING_MACHINE: usize = usize::MAX;
+
+/// Like [`parse_trace`], but allows to (i) set integration delays at the
+/// client and server, (ii) parse padding already in the trace, (iii) replay
+/// the trace causally, (iv) put all flows in one, and (v) seed the RNG, see
+/// [`ParseArgs`].
+pub fn parse_trace_advanced(trace: &str, args: &ParseArgs) -> Result<SimQueue, ParseError> {
+    let mut queue = QueueBuilder::new(args);
+    for (i, l) in trace.lines().enumerate() {
+        parse_line(&mut queue, l).map_err(|kind| ParseError { line: i + 1, kind })?;
+    }
+    Ok(queue.finish())
+}
+
+/// Like [`parse_trace_advanced`], but reads the trace line by line from a
+/// reader (e.g., a file, a decompressing stream, or stdin) instead of from
+/// memory.
+pub fn parse_trace_reader<R: BufRead>(
+    mut reader: R,
+    args: &ParseArgs,
+) -> Result<SimQueue, ParseError> {
+    let mut queue = QueueBuilder::new(args);
+    let mut buf = String::new();
+    let mut line = 0;
+    loop {
+        line += 1;
+        buf.clear();
+        let read = reader.read_line(&mut buf).map_err(|e| ParseError {
+            line,
+            kind: ParseErrorKind::Io(e.kind()),
+        })?;
+        if read == 0 {
+            return Ok(queue.finish());
+        }
+        parse_line(&mut queue, &buf).map_err(|kind| ParseError { line, kind })?;
+    }
+}
+
+// parse one line of a trace, pushing any packet onto the queue
+fn parse_line(queue: &mut QueueBuilder, l: &str) -> Result<(), ParseErrorKind> {
+    let l = l.trim();
+    if l.is_empty() {
+        return Ok(());
+    }
+    let parts: Vec<&str> = l.split(',').map(str::trim).collect();
+    if parts.len() != 3 && parts.len() != 4 {
+        return Err(ParseErrorKind::InvalidFormat(l.to_string()));
+    }
+
+    let offset = Duration::from_nanos(
+        parts[0]
+            .parse::<u64>()
+            .map_err(|_| ParseErrorKind::InvalidTimestamp(parts[0].to_string()))?,
+    );
+    let size = parts[2]
+        .parse::<u16>()
+        .map_err(|_| ParseErrorKind::InvalidSize(parts[2].to_string()))?;
+
+    let (sent, padding) = match parts[1] {
+        "s" | "sn" => (true, false),
+        "r" | "rn" => (false, false),
+        "sp" => (true, true),
+        "rp" => (false, true),
+        d => return Err(ParseErrorKind::InvalidDirection(d.to_string())),
+    };
+    if padding && !queue.args.padding {
+        return Ok(());
+    }
+    let flow = match parts.get(3) {
+        Some(_) if queue.args.single_flow => 0,
+        Some(f) => f
+            .parse::<usize>()
+            .map_err(|_| ParseErrorKind::InvalidFlow(f.to_string()))?,
+        None => 0,
+    };
+    queue.push(offset, sent, padding, size, flow)
+}
+
+/// Builds a [`SimQueue`] from the packets of a trace, pushed in order of time.
+pub(crate) struct QueueBuilder<'a> {
+    sq: SimQueue,
+    args: &'a ParseArgs<'a>,
+    // for causal replay: the id of the next non-padding sent, and the
+    // packets sent and received by the client in each flow
+    next_id: u64,
+    causal: HashMap<usize, CausalFlow>,
+    rng: StdRng,
+}
+
+// for causal replay in a flow: the arrival times at the server (sorted) and
+// ids of non-padding sent by the client, and the time and id of the last
+// non-padding received by the client
+#[derive(Default)]
+struct CausalFlow {
+    client_sent: Vec<(SimTime, u64)>,
+    client_received: Option<(SimTime, u64)>,
+}
+
+impl<'a> QueueBuilder<'a> {
+    pub(crate) fn new(args: &'a ParseArgs<'a>) -> Self {
+        Self {
+            sq: SimQueue::new(),
+            args,
+            next_id: 0,
+            causal: HashMap::new(),
+            rng: rng::new(args.seed),
+        }
+    }
+
+    pub(crate) fn finish(self) -> SimQueue {
+        self.sq
+    }
+
+    /// Push a packet in the flow sent (if sent is true) or received by the
+    /// client at the given time relative to the start of the trace, as in
+    /// [`parse_trace_advanced`].
+    pub(crate) fn push(
+        &mut self,
+        offset: Duration,
+        sent: bool,
+        padding: bool,
+        size: u16,
+        flow: usize,
+    ) -> Result<(), ParseErrorKind> {
+        rng::swap(&mut self.rng);
+        let result = self.push_packet(offset, sent, padding, size, flow);
+        rng::swap(&mut self.rng);
+        result
+    }
+
+    fn push_packet(
+        &mut self,
+        offset: Duration,
+        sent: bool,
+        padding: bool,
+        size: u16,
+        flow: usize,
+    ) -> Result<(), ParseErrorKind> {
+        let args = self.args;
+        // delays added to the timestamp must also stay within simulated time
+        let invalid = || ParseErrorKind::InvalidTimestamp(offset.as_nanos().to_string());
+        let timestamp = SimTime::ZERO.checked_add(offset).ok_or_else(invalid)?;
+
+        if padding {
+            // padding is sent at the given time by the client, or by the
+            // server like received non-padding below, without any integration
+            // delays (it was never an action of a machine)
+            let time = if sent {
+                timestamp
+            } else {
+                timestamp
+                    .checked_sub(args.network.nominal_delay(false, offset, size))
+                    .ok_or(ParseErrorKind::ReceivedTooEarly)?
+            };
+            let event = TriggerEvent::PaddingSent {
+                bytes_sent: size,
+                machine: MachineId::from_raw(TRACE_PADDING_MACHINE),
+            };
+            let event = new_event(event, sent, time, Duration::from_micros(0), flow, None);
+            self.sq.push_sim(event, Reverse(time));
+            return Ok(());
+        }
+
+        // client sent at the given time, or sent by server delay time ago,
+        // plus the time it took to transmit the packet on the link
+        let time = if sent {
+            timestamp
+        } else {
+            timestamp
+                .checked_sub(args.network.nominal_delay(false, offset, size))
+                .ok_or(ParseErrorKind::ReceivedTooEarly)?
+        };
+        // but reported to the Maybenot framework with delay
+        let integration = if sent {
+            args.client_integration
+        } else {
+            args.server_integration
+        };
+        let reporting_delay = integration
+            .map(|i| i.reporting_delay.sample())
+            .unwrap_or(Duration::from_micros(0));
+        let event = TriggerEvent::NonPaddingSent { bytes_sent: size };
+
+        let reported = time.checked_add(reporting_delay).ok_or_else(invalid)?;
+        if !args.causal {
+            let event = new_event(event, sent, reported, reporting_delay, flow, None);
+            self.sq.push_sim(event, Reverse(reported));
+            return Ok(());
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let causal = self.causal.entry(flow).or_default();
+        let cause = if sent {
+            // the client received the last received packet before sending
+            let cause = causal.client_received;
+            let arrival = timestamp
+                .checked_add(args.network.nominal_delay(true, offset, size))
+                .ok_or_else(invalid)?;
+            let i = causal.client_sent.partition_point(|(a, _)| *a <= arrival);
+            causal.client_sent.insert(i, (arrival, id));
+            cause
+        } else {
+            causal.client_received = Some((timestamp, id));
+            // the last packet from the client to arrive before sending
+            let i = causal.client_sent.partition_point(|(a, _)| *a <= time);
+            i.checked_sub(1).map(|i| causal.client_sent[i])
+        };
+        let event = new_event(event, sent, reported, reporting_delay, flow, Some(id));
+        match cause {
+            Some((received, cause)) => {
+                let gap = time.saturating_duration_since(received);
+                self.sq.push_after(cause, gap, event)
+            }
+            None => self.sq.push_sim(event, Reverse(reported)),
+        }
+
+        Ok(())
+    }
+}
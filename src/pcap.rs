@@ -0,0 +1,324 @@
+//! Parsing packet captures (pcap or pcapng) into a [`SimQueue`].
+
+use std::{
+    error::Error,
+    fmt,
+    io::{BufRead, BufReader, Read},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use pcap_file::{
+    pcap::PcapReader,
+    pcapng::{blocks::interface_description::InterfaceDescriptionOption, Block, PcapNgReader},
+    DataLink,
+};
+
+use crate::{
+    parse::{ParseArgs, ParseErrorKind, QueueBuilder},
+    queue::SimQueue,
+};
+
+/// How to tell the client's packets apart in a capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcapClient {
+    /// Packets from the address are sent by the client, packets to the
+    /// address are received by the client.
+    Addr(IpAddr),
+    /// Packets from the (UDP or TCP) port are sent by the client, packets to
+    /// the port are received by the client.
+    Port(u16),
+}
+
+/// An error from parsing a capture.
+#[derive(Debug)]
+pub enum PcapError {
+    /// The capture could not be read as pcap or pcapng.
+    Read(Box<dyn Error + Send + Sync>),
+    /// The link type of the capture is not Ethernet, Linux cooked capture, or
+    /// raw IP.
+    UnsupportedLinkType(u32),
+    /// A packet (1-indexed, counting all packets in the capture) could not be
+    /// added to the queue.
+    Packet { number: usize, kind: ParseErrorKind },
+}
+
+impl fmt::Display for PcapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PcapError::Read(e) => write!(f, "failed to read capture: {}", e),
+            PcapError::UnsupportedLinkType(l) => write!(f, "unsupported link type {}", l),
+            PcapError::Packet { number, kind } => {
+                write!(f, "packet {}: {}", number, kind)
+            }
+        }
+    }
+}
+
+impl Error for PcapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PcapError::Read(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<pcap_file::PcapError> for PcapError {
+    fn from(e: pcap_file::PcapError) -> Self {
+        PcapError::Read(Box::new(e))
+    }
+}
+
+// the magic number of a pcapng section header block (same in either byte order)
+const PCAPNG_MAGIC: [u8; 4] = [0x0a, 0x0d, 0x0d, 0x0a];
+
+/// Parse a packet capture into a [`SimQueue`] for use with
+/// [`sim`](crate::sim), like [`parse_trace_advanced`](crate::parse_trace_advanced).
+///
+/// The capture can be in the pcap or pcapng format, with Ethernet, Linux
+/// cooked capture (SLL), or raw IP link types. Every UDP or TCP packet over
+/// IPv4 or IPv6 to or from the client is sent or received by the client, with
+/// the size of the IP packet. All other packets are skipped. Time is relative
+/// to the first packet of the client in the capture, and packets timestamped
+/// before it are an error. Packets in pcapng simple packet blocks have no
+/// timestamps and are also skipped.
+pub fn parse_pcap<R: Read>(
+    reader: R,
+    client: PcapClient,
+    args: &ParseArgs,
+) -> Result<SimQueue, PcapError> {
+    let mut reader = BufReader::new(reader);
+    let magic = reader
+        .fill_buf()
+        .map_err(|e| PcapError::Read(Box::new(e)))?;
+    let mut packets = Packets::new(client, args);
+
+    if magic.starts_with(&PCAPNG_MAGIC) {
+        let mut pcapng = PcapNgReader::new(reader)?;
+        while let Some(block) = pcapng.next_block() {
+            // owned, to look up the interface of the packet in the reader
+            let Block::EnhancedPacket(packet) = block?.into_owned() else {
+                continue;
+            };
+            let interface = pcapng
+                .packet_interface(&packet)
+                .ok_or_else(|| PcapError::Read("packet for unknown interface".into()))?;
+            // pcap-file reads the raw timestamp as nanoseconds, but it is in
+            // the resolution of the interface: microseconds by default
+            let resolution = interface
+                .options
+                .iter()
+                .find_map(|o| match o {
+                    InterfaceDescriptionOption::IfTsResol(r) => Some(*r),
+                    _ => None,
+                })
+                .unwrap_or(6);
+            let timestamp = scale_timestamp(packet.timestamp.as_nanos(), resolution);
+            packets.push(interface.linktype, timestamp, &packet.data)?;
+        }
+    } else {
+        let mut pcap = PcapReader::new(reader)?;
+        let linktype = pcap.header().datalink;
+        while let Some(packet) = pcap.next_packet() {
+            let packet = packet?;
+            packets.push(linktype, packet.timestamp, &packet.data)?;
+        }
+    }
+
+    Ok(packets.queue.finish())
+}
+
+// scale a raw pcapng timestamp in the given if_tsresol resolution to a duration
+fn scale_timestamp(raw: u128, resolution: u8) -> Duration {
+    let nanos = if resolution & 0x80 == 0 {
+        // negative power of 10
+        let exp = (resolution & 0x7f) as u32;
+        if exp <= 9 {
+            raw * 10u128.pow(9 - exp)
+        } else {
+            raw / 10u128.pow(exp - 9)
+        }
+    } else {
+        // negative power of 2
+        (raw * 1_000_000_000) >> (resolution & 0x7f)
+    };
+    Duration::from_nanos(nanos as u64)
+}
+
+// the packets of the client in a capture, pushed onto a queue
+struct Packets<'a> {
+    queue: QueueBuilder<'a>,
+    client: PcapClient,
+    start: Option<Duration>,
+    number: usize,
+}
+
+impl<'a> Packets<'a> {
+    fn new(client: PcapClient, args: &'a ParseArgs<'a>) -> Self {
+        Self {
+            queue: QueueBuilder::new(args),
+            client,
+            start: None,
+            number: 0,
+        }
+    }
+
+    fn push(
+        &mut self,
+        linktype: DataLink,
+        timestamp: Duration,
+        data: &[u8],
+    ) -> Result<(), PcapError> {
+        self.number += 1;
+        let ip = match linktype {
+            DataLink::ETHERNET => ethernet_payload(data),
+            DataLink::LINUX_SLL => sll_payload(data),
+            DataLink::RAW | DataLink::IPV4 | DataLink::IPV6 => Some(data),
+            l => return Err(PcapError::UnsupportedLinkType(l.into())),
+        };
+        let Some(packet) = ip.and_then(IpPacket::parse) else {
+            return Ok(());
+        };
+
+        let sent = match self.client {
+            PcapClient::Addr(addr) if packet.src == addr => true,
+            PcapClient::Addr(addr) if packet.dst == addr => false,
+            PcapClient::Port(port) if packet.ports.map(|p| p.0) == Some(port) => true,
+            PcapClient::Port(port) if packet.ports.map(|p| p.1) == Some(port) => false,
+            _ => return Ok(()),
+        };
+
+        let err = |kind| PcapError::Packet {
+            number: self.number,
+            kind,
+        };
+        let start = *self.start.get_or_insert(timestamp);
+        let offset = timestamp.checked_sub(start).ok_or_else(|| {
+            let secs = format!("{}.{:09}", timestamp.as_secs(), timestamp.subsec_nanos());
+            err(ParseErrorKind::InvalidTimestamp(secs))
+        })?;
+        self.queue
+            .push(offset, sent, false, packet.size, 0)
+            .map_err(err)
+    }
+}
+
+// the IP packet in an Ethernet frame, skipping any VLAN tags
+fn ethernet_payload(data: &[u8]) -> Option<&[u8]> {
+    let mut offset = 12;
+    loop {
+        let ethertype: [u8; 2] = data.get(offset..offset + 2)?.try_into().ok()?;
+        match u16::from_be_bytes(ethertype) {
+            // 802.1Q and 802.1ad
+            0x8100 | 0x88a8 => offset += 4,
+            _ => return ip_payload(ethertype, data.get(offset + 2..)?),
+        }
+    }
+}
+
+// the IP packet in a Linux cooked capture (SLL) frame
+fn sll_payload(data: &[u8]) -> Option<&[u8]> {
+    ip_payload(data.get(14..16)?.try_into().ok()?, data.get(16..)?)
+}
+
+// the payload if the ethertype is IPv4 or IPv6
+fn ip_payload(ethertype: [u8; 2], data: &[u8]) -> Option<&[u8]> {
+    match u16::from_be_bytes(ethertype) {
+        0x0800 | 0x86dd => Some(data),
+        _ => None,
+    }
+}
+
+// the parts of a UDP or TCP packet over IP we care about
+struct IpPacket {
+    src: IpAddr,
+    dst: IpAddr,
+    // source and destination ports, if in the packet (not for fragments)
+    ports: Option<(u16, u16)>,
+    // the size of the IP packet (as sent, not as captured)
+    size: u16,
+}
+
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+
+impl IpPacket {
+    fn parse(data: &[u8]) -> Option<Self> {
+        match data.first()? >> 4 {
+            4 => Self::parse_v4(data),
+            6 => Self::parse_v6(data),
+            _ => None,
+        }
+    }
+
+    fn parse_v4(data: &[u8]) -> Option<Self> {
+        let header_len = (data.first()? & 0x0f) as usize * 4;
+        let size = u16::from_be_bytes(data.get(2..4)?.try_into().ok()?);
+        let fragment_offset = u16::from_be_bytes(data.get(6..8)?.try_into().ok()?) & 0x1fff;
+        let protocol = *data.get(9)?;
+        let src: [u8; 4] = data.get(12..16)?.try_into().ok()?;
+        let dst: [u8; 4] = data.get(16..20)?.try_into().ok()?;
+        if protocol != TCP && protocol != UDP {
+            return None;
+        }
+        let ports = if fragment_offset == 0 {
+            ports(data.get(header_len..)?)
+        } else {
+            None
+        };
+        Some(Self {
+            src: IpAddr::V4(Ipv4Addr::from(src)),
+            dst: IpAddr::V4(Ipv4Addr::from(dst)),
+            ports,
+            size,
+        })
+    }
+
+    fn parse_v6(data: &[u8]) -> Option<Self> {
+        let payload_len = u16::from_be_bytes(data.get(4..6)?.try_into().ok()?);
+        let src: [u8; 16] = data.get(8..24)?.try_into().ok()?;
+        let dst: [u8; 16] = data.get(24..40)?.try_into().ok()?;
+
+        // skip extension headers to get to the transport protocol
+        let mut next_header = *data.get(6)?;
+        let mut offset = 40;
+        let mut ports_in_packet = true;
+        loop {
+            match next_header {
+                TCP | UDP => break,
+                // hop-by-hop, routing, and destination options
+                0 | 43 | 60 => {
+                    next_header = *data.get(offset)?;
+                    offset += (*data.get(offset + 1)? as usize + 1) * 8;
+                }
+                // fragment: only the first fragment has the transport header
+                44 => {
+                    let fragment_offset =
+                        u16::from_be_bytes(data.get(offset + 2..offset + 4)?.try_into().ok()?) >> 3;
+                    ports_in_packet &= fragment_offset == 0;
+                    next_header = *data.get(offset)?;
+                    offset += 8;
+                }
+                _ => return None,
+            }
+        }
+
+        Some(Self {
+            src: IpAddr::V6(Ipv6Addr::from(src)),
+            dst: IpAddr::V6(Ipv6Addr::from(dst)),
+            ports: ports_in_packet
+                .then(|| ports(data.get(offset..)?))
+                .flatten(),
+            size: payload_len.saturating_add(40),
+        })
+    }
+}
+
+// the source and destination ports of a UDP or TCP header
+fn ports(transport: &[u8]) -> Option<(u16, u16)> {
+    Some((
+        u16::from_be_bytes(transport.get(0..2)?.try_into().ok()?),
+        u16::from_be_bytes(transport.get(2..4)?.try_into().ok()?),
+    ))
+}
@@ -1,20 +1,17 @@
 //! Functions for peeking at the possible next events in the simulation.
 
-use std::{
-    collections::HashMap,
-    time::{Duration, Instant},
-};
+use std::{collections::HashMap, time::Duration};
 
 use maybenot::{event::Event, framework::MachineId, machine::Machine};
 
-use crate::{queue::SimQueue, ScheduledAction, SimEvent, SimState};
+use crate::{queue::SimQueue, time::SimTime, ScheduledAction, SimEvent, SimState};
 
 pub fn peek_queue<M: AsRef<[Machine]>>(
-    sq: &mut SimQueue,
-    client: &mut SimState<M>,
-    server: &mut SimState<M>,
+    sq: &SimQueue,
+    client: &SimState<M>,
+    server: &SimState<M>,
     earliest: Duration,
-    current_time: Instant,
+    current_time: SimTime,
 ) -> (Duration, Option<SimEvent>) {
     // easy: no queue to consider
     if sq.is_empty() {
@@ -95,10 +92,10 @@ pub fn peek_queue<M: AsRef<[Machine]>>(
 // Here be dragons: surprisingly annoying function to get right and fast.
 // Closely tied to how SimQueue is implemented.
 fn peek_queue_earliest_side(
-    sq: &mut SimQueue,
-    blocking_until: &Instant,
+    sq: &SimQueue,
+    blocking_until: &SimTime,
     blocking_bypassable: bool,
-    current_time: Instant,
+    current_time: SimTime,
     is_client: bool,
 ) -> (Duration, Option<SimEvent>) {
     // OK, bummer, we have to peek for the next blocking and nonblocking: note
@@ -155,7 +152,7 @@ fn peek_queue_earliest_side(
 pub fn peek_scheduled(
     scheduled_c: &HashMap<MachineId, ScheduledAction>,
     scheduled_s: &HashMap<MachineId, ScheduledAction>,
-    current_time: Instant,
+    current_time: SimTime,
 ) -> Duration {
     // there are at most one scheduled action per machine, so we can just
     // iterate over all of them quickly
@@ -182,9 +179,9 @@ pub fn peek_scheduled(
 }
 
 pub fn peek_blocked_exp(
-    blocking_c: &Instant,
-    blocking_s: &Instant,
-    current_time: Instant,
+    blocking_c: &SimTime,
+    blocking_s: &SimTime,
+    current_time: SimTime,
 ) -> Duration {
     // no blocking?
     if current_time > *blocking_c && current_time > *blocking_s {
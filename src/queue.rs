@@ -2,13 +2,14 @@
 
 use std::{
     cmp::Reverse,
-    time::{Duration, Instant},
+    collections::{BTreeMap, HashMap},
+    time::Duration,
 };
 
 use maybenot::framework::TriggerEvent;
 use priority_queue::PriorityQueue;
 
-use crate::SimEvent;
+use crate::{rng, time::SimTime, SimEvent};
 
 /// SimQueue represents the queue of events that are to be processed by the
 /// simulator. It is a wrapper around an EventQueue for the client and an
@@ -19,6 +20,9 @@ use crate::SimEvent;
 pub struct SimQueue {
     client: EventQueue,
     server: EventQueue,
+    // for causal replay: events waiting for the packet with an id to be
+    // received, with the time to wait after it was received
+    dependent: HashMap<u64, Vec<(Duration, SimEvent)>>,
 }
 
 impl Default for SimQueue {
@@ -32,6 +36,7 @@ impl SimQueue {
         SimQueue {
             client: EventQueue::new(),
             server: EventQueue::new(),
+            dependent: HashMap::new(),
         }
     }
 
@@ -47,32 +52,60 @@ impl SimQueue {
         &mut self,
         event: TriggerEvent,
         is_client: bool,
-        time: Instant,
+        time: SimTime,
         delay: Duration,
-        priority: Reverse<Instant>,
+        priority: Reverse<SimTime>,
+    ) {
+        self.push_sim(new_event(event, is_client, time, delay, 0, None), priority);
+    }
+
+    /// The number of events waiting for packets to be received before they
+    /// are queued, see [`ParseArgs::causal`](crate::ParseArgs::causal). Not
+    /// included in [`len`](Self::len).
+    pub fn dependent_len(&self) -> usize {
+        self.dependent.values().map(Vec::len).sum()
+    }
+
+    // like push, but with the id of the packet (if any) for causal replay
+    pub(crate) fn push_with_id(
+        &mut self,
+        event: TriggerEvent,
+        is_client: bool,
+        time: SimTime,
+        delay: Duration,
+        id: Option<u64>,
     ) {
         self.push_sim(
-            SimEvent {
-                event,
-                time,
-                delay,
-                client: is_client,
-                bypass: false,
-                replace: false,
-                fuzz: fastrand::i32(..),
-            },
-            priority,
+            new_event(event, is_client, time, delay, 0, id),
+            Reverse(time),
         );
     }
 
-    pub fn push_sim(&mut self, item: SimEvent, priority: Reverse<Instant>) {
+    // push a sent packet (with an id) to be queued the gap after the packet
+    // with the cause id is received, for causal replay: its time is set once
+    // queued
+    pub(crate) fn push_after(&mut self, cause: u64, gap: Duration, event: SimEvent) {
+        self.dependent.entry(cause).or_default().push((gap, event));
+    }
+
+    // queue the events waiting for the packet with the id, received at the
+    // given time (without any reporting delay)
+    pub(crate) fn release(&mut self, id: u64, received: SimTime) {
+        for (gap, mut event) in self.dependent.remove(&id).unwrap_or_default() {
+            let time = received + gap + event.delay;
+            event.time = time;
+            self.push_sim(event, Reverse(time));
+        }
+    }
+
+    pub fn push_sim(&mut self, item: SimEvent, priority: Reverse<SimTime>) {
         match item.client {
             true => self.client.push(item, priority),
             false => self.server.push(item, priority),
         }
     }
 
-    pub fn peek(&self) -> Option<(&SimEvent, &Reverse<Instant>)> {
+    pub fn peek(&self) -> Option<(&SimEvent, &Reverse<SimTime>)> {
         match self.len() {
             0 => None,
             _ => {
@@ -88,6 +121,78 @@ impl SimQueue {
         }
     }
 
+    // mark all events in the queue (also those waiting) as input to the
+    // simulator
+    pub(crate) fn mark_input(&mut self) {
+        self.client.mark_input();
+        self.server.mark_input();
+        for (_, e) in self.dependent.values_mut().flatten() {
+            e.input = true;
+        }
+    }
+
+    // split the queue into one queue per flow, by the flow id of the events:
+    // events the simulator queues in the queue of a flow are in that flow,
+    // whatever their flow field
+    pub(crate) fn into_flows(self) -> BTreeMap<usize, SimQueue> {
+        let mut flows: BTreeMap<usize, SimQueue> = BTreeMap::new();
+        let events = self.client.into_events().chain(self.server.into_events());
+        for (e, priority) in events {
+            flows.entry(e.flow).or_default().push_sim(e, priority);
+        }
+        for (cause, waiting) in self.dependent {
+            for (gap, e) in waiting {
+                let dependent = &mut flows.entry(e.flow).or_default().dependent;
+                dependent.entry(cause).or_default().push((gap, e));
+            }
+        }
+        flows
+    }
+
+    // split the queue into the events at the client and at the server
+    pub(crate) fn into_sides(self) -> (SimQueue, SimQueue) {
+        let mut client = SimQueue {
+            client: self.client,
+            ..SimQueue::new()
+        };
+        let mut server = SimQueue {
+            server: self.server,
+            ..SimQueue::new()
+        };
+        for (cause, waiting) in self.dependent {
+            for (gap, e) in waiting {
+                let side = if e.client { &mut client } else { &mut server };
+                side.dependent.entry(cause).or_default().push((gap, e));
+            }
+        }
+        (client, server)
+    }
+
+    // move the events of the queue of a flow into this queue, as in that flow
+    pub(crate) fn append_flow(&mut self, other: SimQueue, flow: usize) {
+        let events = other.client.into_events().chain(other.server.into_events());
+        for (mut e, priority) in events {
+            e.flow = flow;
+            self.push_sim(e, priority);
+        }
+        for (cause, waiting) in other.dependent {
+            let dependent = self.dependent.entry(cause).or_default();
+            dependent.extend(waiting.into_iter().map(|(gap, mut e)| {
+                e.flow = flow;
+                (gap, e)
+            }));
+        }
+    }
+
+    // the largest (integration) delay of any event in the queue
+    pub(crate) fn max_delay(&self) -> Duration {
+        let dependent = self.dependent.values().flatten().map(|(_, e)| e.delay);
+        dependent.fold(
+            self.client.max_delay().max(self.server.max_delay()),
+            Duration::max,
+        )
+    }
+
     pub fn remove(&mut self, item: &SimEvent) {
         match item.client {
             true => self.client.remove(item),
@@ -99,7 +204,7 @@ impl SimQueue {
         &self,
         blocking_bypassable: bool,
         is_client: bool,
-    ) -> Option<(&SimEvent, &Reverse<Instant>)> {
+    ) -> Option<(&SimEvent, &Reverse<SimTime>)> {
         match is_client {
             true => peak_blocking(&self.client, blocking_bypassable),
             false => peak_blocking(&self.server, blocking_bypassable),
@@ -110,7 +215,7 @@ impl SimQueue {
         &self,
         blocking_bypassable: bool,
         is_client: bool,
-    ) -> Option<(&SimEvent, &Reverse<Instant>)> {
+    ) -> Option<(&SimEvent, &Reverse<SimTime>)> {
         match is_client {
             true => peak_nonblocking(&self.client, blocking_bypassable),
             false => peak_nonblocking(&self.server, blocking_bypassable),
@@ -121,7 +226,7 @@ impl SimQueue {
 fn peak_blocking(
     queue: &EventQueue,
     blocking_bypassable: bool,
-) -> Option<(&SimEvent, &Reverse<Instant>)> {
+) -> Option<(&SimEvent, &Reverse<SimTime>)> {
     if blocking_bypassable {
         // only blocking events are then blocking
         queue.peek_blocking()
@@ -141,7 +246,7 @@ fn peak_blocking(
 fn peak_nonblocking(
     queue: &EventQueue,
     blocking_bypassable: bool,
-) -> Option<(&SimEvent, &Reverse<Instant>)> {
+) -> Option<(&SimEvent, &Reverse<SimTime>)> {
     if blocking_bypassable {
         // if the current blocking is not bypassable, then we need to
         // consider blocking_bypassable as a nonblocking event
@@ -167,9 +272,9 @@ fn peak_nonblocking(
 /// 3. nonblocking: events that are always not blocking.
 #[derive(Debug, Clone)]
 struct EventQueue {
-    blocking: PriorityQueue<SimEvent, Reverse<Instant>>,
-    blocking_bypassable: PriorityQueue<SimEvent, Reverse<Instant>>,
-    nonblocking: PriorityQueue<SimEvent, Reverse<Instant>>,
+    blocking: PriorityQueue<SimEvent, Reverse<SimTime>>,
+    blocking_bypassable: PriorityQueue<SimEvent, Reverse<SimTime>>,
+    nonblocking: PriorityQueue<SimEvent, Reverse<SimTime>>,
 }
 
 impl EventQueue {
@@ -185,7 +290,7 @@ impl EventQueue {
         self.blocking.len() + self.blocking_bypassable.len() + self.nonblocking.len()
     }
 
-    pub fn push(&mut self, item: SimEvent, priority: Reverse<Instant>) {
+    pub fn push(&mut self, item: SimEvent, priority: Reverse<SimTime>) {
         match item.event {
             TriggerEvent::NonPaddingSent { .. } | TriggerEvent::PaddingSent { .. } => {
                 match item.bypass {
@@ -199,7 +304,7 @@ impl EventQueue {
         }
     }
 
-    pub fn peek(&self) -> Option<(&SimEvent, &Reverse<Instant>)> {
+    pub fn peek(&self) -> Option<(&SimEvent, &Reverse<SimTime>)> {
         match self.len() {
             0 => None,
             _ => {
@@ -236,22 +341,55 @@ impl EventQueue {
         }
     }
 
-    pub fn peek_blocking(&self) -> Option<(&SimEvent, &Reverse<Instant>)> {
+    fn mark_input(&mut self) {
+        for queue in [
+            &mut self.blocking,
+            &mut self.blocking_bypassable,
+            &mut self.nonblocking,
+        ] {
+            *queue = std::mem::take(queue)
+                .into_iter()
+                .map(|(mut e, p)| {
+                    e.input = true;
+                    (e, p)
+                })
+                .collect();
+        }
+    }
+
+    fn into_events(self) -> impl Iterator<Item = (SimEvent, Reverse<SimTime>)> {
+        self.blocking
+            .into_iter()
+            .chain(self.blocking_bypassable)
+            .chain(self.nonblocking)
+    }
+
+    fn max_delay(&self) -> Duration {
+        self.blocking
+            .iter()
+            .chain(self.blocking_bypassable.iter())
+            .chain(self.nonblocking.iter())
+            .map(|(e, _)| e.delay)
+            .max()
+            .unwrap_or_default()
+    }
+
+    pub fn peek_blocking(&self) -> Option<(&SimEvent, &Reverse<SimTime>)> {
         self.blocking.peek()
     }
 
-    pub fn peek_blocking_bypassable(&self) -> Option<(&SimEvent, &Reverse<Instant>)> {
+    pub fn peek_blocking_bypassable(&self) -> Option<(&SimEvent, &Reverse<SimTime>)> {
         self.blocking_bypassable.peek()
     }
 
-    pub fn peek_nonblocking(&self) -> Option<(&SimEvent, &Reverse<Instant>)> {
+    pub fn peek_nonblocking(&self) -> Option<(&SimEvent, &Reverse<SimTime>)> {
         self.nonblocking.peek()
     }
 }
 
 fn before(
-    a: Option<(&SimEvent, &Reverse<Instant>)>,
-    b: Option<(&SimEvent, &Reverse<Instant>)>,
+    a: Option<(&SimEvent, &Reverse<SimTime>)>,
+    b: Option<(&SimEvent, &Reverse<SimTime>)>,
 ) -> bool {
     // is a before b?
     match a {
@@ -262,3 +400,27 @@ fn before(
         None => false,
     }
 }
+
+pub(crate) fn new_event(
+    event: TriggerEvent,
+    is_client: bool,
+    time: SimTime,
+    delay: Duration,
+    flow: usize,
+    id: Option<u64>,
+) -> SimEvent {
+    SimEvent {
+        event,
+        time,
+        delay,
+        client: is_client,
+        dropped: false,
+        bypass: false,
+        replace: false,
+        input: false,
+        id,
+        flow,
+        relay: None,
+        fuzz: rng::fuzz(),
+    }
+}
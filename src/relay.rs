@@ -0,0 +1,65 @@
+//! Relays on a multi-hop path between the client and server, e.g., the
+//! middle relay of a Tor circuit.
+
+use std::time::Duration;
+
+use maybenot::machine::Machine;
+
+use crate::{integration::Integration, network::NetworkModel};
+
+/// A relay on the path between the client and server, see
+/// [`SimulatorArgs::relays`](crate::SimulatorArgs::relays).
+///
+/// Non-padding is forwarded by the relay as soon as it is received, while
+/// padding only travels one hop: padding sent to the relay ends there (like
+/// padding cells in Tor addressed to a hop). The relay runs machines on each
+/// of its two links: the machines on the link toward the client only see
+/// (and pad and block) the packets sent and received on that link, like
+/// padding machines at a middle relay in Tor, and likewise toward the server.
+///
+/// Traces are parsed with a model of the whole path, e.g., a
+/// [`Network`](crate::network::Network) with the delay of all hops.
+#[derive(Clone, Debug)]
+pub struct Relay<'a> {
+    /// The machines on the link toward the client.
+    pub client_machines: &'a [Machine],
+    /// The machines on the link toward the server.
+    pub server_machines: &'a [Machine],
+    /// The network from the relay to the next node toward the server (the
+    /// next relay or the server).
+    pub network: &'a dyn NetworkModel,
+    pub max_padding_frac: f64,
+    pub max_blocking_frac: f64,
+    pub integration: Option<&'a Integration>,
+}
+
+impl<'a> Relay<'a> {
+    pub fn new(network: &'a dyn NetworkModel) -> Self {
+        Self {
+            client_machines: &[],
+            server_machines: &[],
+            network,
+            max_padding_frac: 0.0,
+            max_blocking_frac: 0.0,
+            integration: None,
+        }
+    }
+}
+
+// the nominal delay over the networks of a path (from the client to the
+// server) for a packet sent by the client (if client is true) or the server,
+// as NetworkModel::nominal_delay
+pub(crate) fn path_delay(
+    networks: &[Box<dyn NetworkModel>],
+    client: bool,
+    time: Duration,
+    size: u16,
+) -> Duration {
+    let hops: Vec<_> = match client {
+        true => networks.iter().collect(),
+        false => networks.iter().rev().collect(),
+    };
+    hops.into_iter().fold(Duration::ZERO, |delay, network| {
+        delay + network.nominal_delay(client, time + delay, size)
+    })
+}
@@ -0,0 +1,101 @@
+//! Policies for replacing padding with other packets. Padding with the replace
+//! flag set MAY be replaced by a packet sent shortly before or queued to be
+//! sent shortly after. How this works differs between integrations (e.g., if
+//! the integration has an egress queue to look at), so the decision is made by
+//! a pluggable [`ReplacePolicy`].
+
+use std::{fmt::Debug, time::Duration};
+
+/// The default network replace window: the time window in which we can
+/// replace padding with existing padding or non-padding already queued (or
+/// about to be queued up).
+pub const NETWORK_REPLACE_WINDOW: Duration = Duration::from_micros(1);
+
+/// Decides if padding is replaced by another packet. Used by the simulator for
+/// padding with the replace flag set.
+pub trait ReplacePolicy: Debug {
+    /// Padding of padding_size bytes is about to be sent, and the last packet
+    /// (padding or non-padding) of last_sent_size bytes was sent since_last
+    /// ago. Returns true if the padding is replaced by the last sent packet,
+    /// i.e., the padding is not sent.
+    fn replace_with_last_sent(
+        &self,
+        padding_size: u16,
+        last_sent_size: u16,
+        since_last: Duration,
+        window: Duration,
+    ) -> bool;
+
+    /// Padding of padding_size bytes is about to be sent, and non-padding of
+    /// queued_size bytes is queued to be sent until_queued from now (zero if
+    /// it is already waiting, e.g., due to blocking). Returns the number of
+    /// bytes to send the non-padding with in place of the padding, or None if
+    /// the padding is not replaced. Returning more than queued_size bytes
+    /// compensates for the size difference.
+    fn replace_with_queued(
+        &self,
+        padding_size: u16,
+        queued_size: u16,
+        until_queued: Duration,
+        window: Duration,
+    ) -> Option<u16>;
+}
+
+/// The default replace policy: padding is replaced by packets within the
+/// window of equal or smaller size, without compensating for the size
+/// difference. This allows easy constant-rate defenses accounting for
+/// variable-size packets.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EqualOrSmaller;
+
+impl ReplacePolicy for EqualOrSmaller {
+    fn replace_with_last_sent(
+        &self,
+        padding_size: u16,
+        last_sent_size: u16,
+        since_last: Duration,
+        window: Duration,
+    ) -> bool {
+        since_last <= window && last_sent_size <= padding_size
+    }
+
+    fn replace_with_queued(
+        &self,
+        padding_size: u16,
+        queued_size: u16,
+        until_queued: Duration,
+        window: Duration,
+    ) -> Option<u16> {
+        (until_queued <= window && queued_size <= padding_size).then_some(queued_size)
+    }
+}
+
+/// Like [`EqualOrSmaller`], but queued non-padding that replaces padding is
+/// padded up to the size of the padding, so the replacement is
+/// indistinguishable in size from the padding it replaced. The last sent
+/// packet is already on its way and cannot be padded, so it only replaces
+/// padding of the same size.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SizeCompensating;
+
+impl ReplacePolicy for SizeCompensating {
+    fn replace_with_last_sent(
+        &self,
+        padding_size: u16,
+        last_sent_size: u16,
+        since_last: Duration,
+        window: Duration,
+    ) -> bool {
+        since_last <= window && last_sent_size == padding_size
+    }
+
+    fn replace_with_queued(
+        &self,
+        padding_size: u16,
+        queued_size: u16,
+        until_queued: Duration,
+        window: Duration,
+    ) -> Option<u16> {
+        (until_queued <= window && queued_size <= padding_size).then_some(padding_size)
+    }
+}
@@ -0,0 +1,52 @@
+//! The source of randomness in the simulator.
+//!
+//! Each [`Simulator`](crate::Simulator) has its own RNG for all randomness
+//! sampled while simulating (event fuzz, integration delays, network jitter,
+//! and packet loss), seeded with [`SimulatorArgs::seed`](crate::SimulatorArgs::seed).
+//! Parsing a trace samples from its own RNG as well (event fuzz and reporting
+//! delays), seeded with [`ParseArgs::seed`](crate::ParseArgs::seed). Runs with
+//! the same seeds and input give the same output trace, no matter what else
+//! is simulated on the same thread in the meantime.
+//!
+//! Sampling outside of simulating and parsing, e.g., the fuzz of events pushed
+//! directly onto a [`SimQueue`](crate::queue::SimQueue), uses an RNG of the
+//! current thread that can be seeded with [`seed`].
+//!
+//! Note that the Maybenot framework samples from its own RNG that cannot be
+//! seeded, so machines that sample from distributions with a range of values
+//! or that make probabilistic state transitions are not reproducible.
+
+use std::cell::RefCell;
+
+use rand::{rngs::StdRng, SeedableRng};
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Seed the RNG of the current thread, used outside of simulating and
+/// parsing.
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+/// A new RNG from the seed, if any, otherwise from entropy.
+pub(crate) fn new(seed: Option<u64>) -> StdRng {
+    seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64)
+}
+
+/// Exchange the RNG of the current thread with rng: simulators and parsers
+/// swap in their own RNG while sampling, and swap it back out when done.
+pub(crate) fn swap(rng: &mut StdRng) {
+    RNG.with(|current| std::mem::swap(&mut *current.borrow_mut(), rng));
+}
+
+/// Run f with the RNG of the current thread.
+pub(crate) fn with_rng<T>(f: impl FnOnce(&mut StdRng) -> T) -> T {
+    RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
+
+/// A random fuzz value for a [`SimEvent`](crate::SimEvent).
+pub(crate) fn fuzz() -> i32 {
+    with_rng(rand::Rng::gen)
+}
@@ -0,0 +1,68 @@
+//! Observer taps on the wire between the client and server, recording what a
+//! passive network attacker sees there.
+
+use maybenot::framework::TriggerEvent;
+
+use crate::time::SimTime;
+
+/// Where an observer taps the wire, see
+/// [`SimulatorArgs::taps`](crate::SimulatorArgs::taps). The observer sees
+/// every packet sent and received at that point (of all flows), when it is
+/// on the wire: packets dropped on the way to the other side are only seen
+/// by observers before the drop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tap {
+    /// On the wire right after the client.
+    Client,
+    /// On the wire right before the relay (numbered from 1 for the first
+    /// relay after the client, as [`SimEvent::relay`](crate::SimEvent::relay)),
+    /// on its link toward the client. Simulating with a relay that is not on
+    /// the path is an [`InvalidTap`](crate::SimError::InvalidTap) error.
+    Relay(usize),
+    /// On the wire right before the server.
+    Server,
+}
+
+impl Tap {
+    // the hop and end of the hop (true for the client end) of the tap on a
+    // path with the number of hops, if any
+    pub(crate) fn end(&self, hops: usize) -> Option<(usize, bool)> {
+        match *self {
+            Tap::Client => Some((0, true)),
+            Tap::Relay(relay) if relay > 0 && relay < hops => Some((relay - 1, false)),
+            Tap::Relay(_) => None,
+            Tap::Server => Some((hops - 1, false)),
+        }
+    }
+}
+
+/// A packet seen by an observer at a [`Tap`]: like on the wire, padding
+/// cannot be told apart from non-padding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Observed {
+    /// The time the packet passed the tap.
+    pub time: SimTime,
+    /// True if the packet was going from the client toward the server.
+    pub outgoing: bool,
+    /// The size of the packet in bytes.
+    pub size: u16,
+}
+
+impl Observed {
+    // the packet sent or received at the end of a hop (the client end if
+    // client is true) in the event, on the wire at the time
+    pub(crate) fn new(event: &TriggerEvent, client: bool, time: SimTime) -> Option<Self> {
+        let (sent, size) = match *event {
+            TriggerEvent::NonPaddingSent { bytes_sent }
+            | TriggerEvent::PaddingSent { bytes_sent, .. } => (true, bytes_sent),
+            TriggerEvent::NonPaddingRecv { bytes_recv }
+            | TriggerEvent::PaddingRecv { bytes_recv } => (false, bytes_recv),
+            _ => return None,
+        };
+        Some(Self {
+            time,
+            outgoing: client == sent,
+            size,
+        })
+    }
+}
@@ -0,0 +1,126 @@
+//! The virtual clock of the simulator.
+
+use std::{
+    fmt,
+    ops::{Add, AddAssign, Sub, SubAssign},
+    time::Duration,
+};
+
+/// A point in simulated time: nanoseconds since the start of the trace. Times
+/// before the start of the trace are negative, e.g., when the server sent a
+/// packet that the client received at the very start of the trace.
+///
+/// SimTime mirrors the API of [`std::time::Instant`], but is owned by the
+/// simulator: the same trace always gives the same times, and the time of an
+/// event in an output trace can be read directly with [`SimTime::as_nanos`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct SimTime(i64);
+
+impl SimTime {
+    /// The start of the trace.
+    pub const ZERO: SimTime = SimTime(0);
+
+    pub const fn from_nanos(nanos: i64) -> Self {
+        SimTime(nanos)
+    }
+
+    pub const fn as_nanos(&self) -> i64 {
+        self.0
+    }
+
+    pub const fn as_micros(&self) -> i64 {
+        self.0 / 1_000
+    }
+
+    pub const fn as_millis(&self) -> i64 {
+        self.0 / 1_000_000
+    }
+
+    /// The time as a duration since the start of the trace, or None if the
+    /// time is before the start.
+    pub fn since_start(&self) -> Option<Duration> {
+        self.checked_duration_since(SimTime::ZERO)
+    }
+
+    /// The amount of time elapsed from another time to this one, or zero if
+    /// that time is later than this one.
+    pub fn duration_since(&self, earlier: SimTime) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+
+    /// The amount of time elapsed from another time to this one, or None if
+    /// that time is later than this one.
+    pub fn checked_duration_since(&self, earlier: SimTime) -> Option<Duration> {
+        let nanos = self.0.checked_sub(earlier.0)?;
+        u64::try_from(nanos).ok().map(Duration::from_nanos)
+    }
+
+    /// The amount of time elapsed from another time to this one, or zero if
+    /// that time is later than this one.
+    pub fn saturating_duration_since(&self, earlier: SimTime) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<SimTime> {
+        let nanos = i64::try_from(duration.as_nanos()).ok()?;
+        self.0.checked_add(nanos).map(SimTime)
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<SimTime> {
+        let nanos = i64::try_from(duration.as_nanos()).ok()?;
+        self.0.checked_sub(nanos).map(SimTime)
+    }
+}
+
+impl From<Duration> for SimTime {
+    /// The time the duration after the start of the trace.
+    fn from(duration: Duration) -> Self {
+        SimTime::ZERO + duration
+    }
+}
+
+impl Add<Duration> for SimTime {
+    type Output = SimTime;
+
+    fn add(self, rhs: Duration) -> SimTime {
+        self.checked_add(rhs)
+            .expect("overflow when adding duration to time")
+    }
+}
+
+impl AddAssign<Duration> for SimTime {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for SimTime {
+    type Output = SimTime;
+
+    fn sub(self, rhs: Duration) -> SimTime {
+        self.checked_sub(rhs)
+            .expect("overflow when subtracting duration from time")
+    }
+}
+
+impl SubAssign<Duration> for SimTime {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub<SimTime> for SimTime {
+    type Output = Duration;
+
+    /// Like [`SimTime::duration_since`], saturating at zero.
+    fn sub(self, rhs: SimTime) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+impl fmt::Display for SimTime {
+    /// Nanoseconds since the start of the trace.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
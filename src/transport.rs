@@ -0,0 +1,238 @@
+//! A TCP-like transport model, where a congestion window clocked by ACKs from
+//! the other side releases the non-padding sent by the client and server.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+    time::Duration,
+};
+
+use log::debug;
+use maybenot::framework::TriggerEvent;
+
+use crate::{queue::SimQueue, time::SimTime, SimEvent};
+
+/// The size of an ACK in bytes, for its delay over the network.
+pub const ACK_SIZE: u16 = 52;
+
+/// A TCP-like transport model for the non-padding sent by the client and
+/// server, each with their own congestion window (in packets).
+///
+/// Non-padding is only sent with room in the window, otherwise it waits for
+/// an ACK. Every received non-padding packet is ACKed at once by the other
+/// side (the ACKs are not part of the simulated trace). The window starts with
+/// slow start, growing by one packet per ACK up to the slow-start threshold,
+/// and then grows by one packet per window of ACKs (AIMD). A loss halves the
+/// window once the lost packet would have been ACKed, and further losses of
+/// packets sent before then are part of the same loss event. Lost packets
+/// are only retransmitted if the network has an RTO (see
+/// [`Link::rto`](crate::network::Link::rto)): otherwise the loss frees up
+/// room in the window, but the data never reaches the other side. Padding is
+/// not part of the transport (e.g., it is added by a tunnel around the
+/// connection) and never waits for the window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tcp {
+    /// The initial congestion window, in packets.
+    pub initial_window: u32,
+    /// The initial slow-start threshold, in packets.
+    pub initial_ssthresh: u32,
+}
+
+impl Tcp {
+    pub fn new() -> Self {
+        Self {
+            // RFC 6928
+            initial_window: 10,
+            initial_ssthresh: u32::MAX,
+        }
+    }
+}
+
+impl Default for Tcp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// at most this many waiting packets are woken up per ACK: one for the packet
+// ACKed and one for the window growing
+const WAKE_PER_ACK: usize = 2;
+
+// the transport state of the client and server in a simulation
+#[derive(Debug)]
+pub(crate) struct Transport {
+    client: Window,
+    server: Window,
+}
+
+// the congestion window of one side
+#[derive(Debug)]
+struct Window {
+    cwnd: f64,
+    ssthresh: f64,
+    in_flight: u32,
+    // times ACKs (and losses, with the time the lost packet was sent) reach
+    // the sender, not yet counted
+    acks: BinaryHeap<Reverse<(SimTime, Option<SimTime>)>>,
+    // losses of packets sent before this time are part of the last loss event
+    recovery: Option<SimTime>,
+    // non-padding waiting for room in the window, to be woken up by an ACK
+    waiting: VecDeque<SimEvent>,
+    // ACKs not yet counted that can still wake up this many waiting packets
+    wakes: VecDeque<(SimTime, usize)>,
+}
+
+impl Transport {
+    pub(crate) fn new(tcp: &Tcp) -> Self {
+        Self {
+            client: Window::new(tcp),
+            server: Window::new(tcp),
+        }
+    }
+
+    fn window(&mut self, client: bool) -> &mut Window {
+        if client {
+            &mut self.client
+        } else {
+            &mut self.server
+        }
+    }
+
+    /// Returns the non-padding sent at the current time if there is room in
+    /// the window of the sender, otherwise it waits for an ACK.
+    pub(crate) fn send(
+        &mut self,
+        next: SimEvent,
+        current_time: SimTime,
+        sq: &mut SimQueue,
+    ) -> Option<SimEvent> {
+        let window = self.window(next.client);
+        window.count_acks(current_time);
+        if (window.in_flight as f64) < window.cwnd.floor() {
+            window.in_flight += 1;
+            return Some(next);
+        }
+        debug!(
+            "\ttransport: window full @{}, waiting",
+            if next.client { "client" } else { "server" }
+        );
+
+        // woken up by the next ACK that can still wake up packets, if any
+        window.wakes.retain(|(at, _)| *at > current_time);
+        match window.wakes.front_mut() {
+            Some((at, left)) => {
+                wake(next, *at, sq);
+                *left -= 1;
+                if *left == 0 {
+                    window.wakes.pop_front();
+                }
+            }
+            None => window.waiting.push_back(next),
+        }
+        None
+    }
+
+    /// Schedule the ACK for a received non-padding packet (or the loss of a
+    /// sent one) at the sender, waking up packets waiting in its window. The
+    /// ACK takes the nominal delay of the path between the client and server
+    /// (as [`NetworkModel::nominal_delay`](crate::network::NetworkModel::nominal_delay)),
+    /// and the packets are woken up in the queue of the sender.
+    pub(crate) fn ack<'q>(
+        &mut self,
+        next: &SimEvent,
+        nominal_delay: impl Fn(bool, Duration, u16) -> Duration,
+        sq: impl FnOnce(bool) -> &'q mut SimQueue,
+    ) {
+        let (sender, at, lost) = match next.event {
+            // received at the current time, without the reporting delay
+            TriggerEvent::NonPaddingRecv { .. } => {
+                let received = next.time - next.delay;
+                let delay = nominal_delay(next.client, since_start(received), ACK_SIZE);
+                (!next.client, received + delay, None)
+            }
+            // the time the packet would have been ACKed (over the whole
+            // path from where it was lost)
+            TriggerEvent::NonPaddingSent { bytes_sent } if next.dropped => {
+                let sent = next.time - next.delay;
+                let there = sent + nominal_delay(next.client, since_start(sent), bytes_sent);
+                let back = nominal_delay(!next.client, since_start(there), ACK_SIZE);
+                (next.client, there + back, Some(sent))
+            }
+            _ => return,
+        };
+        let sq = sq(sender);
+
+        let window = self.window(sender);
+        window.acks.push(Reverse((at, lost)));
+        let mut left = WAKE_PER_ACK;
+        while left > 0 {
+            let Some(waiting) = window.waiting.pop_front() else {
+                break;
+            };
+            wake(waiting, at, sq);
+            left -= 1;
+        }
+        if left > 0 {
+            window.wakes.push_back((at, left));
+        }
+    }
+
+    /// The packets waiting in the windows of the client and server.
+    pub(crate) fn take_waiting(&mut self) -> impl Iterator<Item = SimEvent> + '_ {
+        self.client
+            .waiting
+            .drain(..)
+            .chain(self.server.waiting.drain(..))
+    }
+}
+
+impl Window {
+    fn new(tcp: &Tcp) -> Self {
+        Self {
+            cwnd: tcp.initial_window.max(1) as f64,
+            ssthresh: tcp.initial_ssthresh as f64,
+            in_flight: 0,
+            acks: BinaryHeap::new(),
+            recovery: None,
+            waiting: VecDeque::new(),
+            wakes: VecDeque::new(),
+        }
+    }
+
+    // count the ACKs (and losses) that have reached the sender by now
+    fn count_acks(&mut self, now: SimTime) {
+        while let Some(Reverse((at, lost))) = self.acks.peek().copied() {
+            if at > now {
+                break;
+            }
+            self.acks.pop();
+            self.in_flight = self.in_flight.saturating_sub(1);
+            if let Some(sent) = lost {
+                // multiplicative decrease, once per loss event
+                if self.recovery.is_none_or(|recovery| sent >= recovery) {
+                    self.ssthresh = (self.cwnd / 2.0).max(2.0);
+                    self.cwnd = self.ssthresh;
+                    self.recovery = Some(at);
+                }
+            } else if self.cwnd < self.ssthresh {
+                // slow start
+                self.cwnd += 1.0;
+            } else {
+                // additive increase
+                self.cwnd += 1.0 / self.cwnd;
+            }
+        }
+    }
+}
+
+// queue a waiting packet to try again at the time of an ACK, sent (reported)
+// as if it was just queued then
+fn wake(mut waiting: SimEvent, at: SimTime, sq: &mut SimQueue) {
+    let time = at + waiting.delay;
+    waiting.time = time;
+    sq.push_sim(waiting, Reverse(time));
+}
+
+fn since_start(time: SimTime) -> Duration {
+    time.since_start().unwrap_or_default()
+}
@@ -0,0 +1,80 @@
+//! Writing simulated traces in the format read by [`parse_trace`](crate::parse_trace).
+
+use std::io::{self, Write};
+
+use maybenot::framework::TriggerEvent;
+
+use crate::{tap::Observed, time::SimTime, SimEvent};
+
+/// Write a simulated trace from the viewpoint of the client (if client is
+/// true) or the server. Events at relays are skipped.
+///
+/// Each sent or received packet is written as a line "time,direction,size\n",
+/// where time is in nanoseconds relative to the first written packet,
+/// direction is "s" (sent), "r" (received), "sp" (sent padding), or "rp"
+/// (received padding), and size is the number of bytes sent or received. All
+/// other events (e.g., blocking) are skipped. Sent packets are written even if
+/// they were dropped on the way to the other side. Packets in a flow other
+/// than flow 0 have the flow as a fourth field, "time,direction,size,flow\n".
+pub fn write_trace<W: Write>(writer: &mut W, trace: &[SimEvent], client: bool) -> io::Result<()> {
+    let mut start: Option<SimTime> = None;
+    for e in trace
+        .iter()
+        .filter(|e| e.client == client && e.relay.is_none())
+    {
+        let (direction, size) = match e.event {
+            TriggerEvent::NonPaddingSent { bytes_sent } => ("s", bytes_sent),
+            TriggerEvent::PaddingSent { bytes_sent, .. } => ("sp", bytes_sent),
+            TriggerEvent::NonPaddingRecv { bytes_recv } => ("r", bytes_recv),
+            TriggerEvent::PaddingRecv { bytes_recv, .. } => ("rp", bytes_recv),
+            _ => continue,
+        };
+        let start = *start.get_or_insert(e.time);
+        write!(
+            writer,
+            "{},{},{}",
+            e.time.duration_since(start).as_nanos(),
+            direction,
+            size
+        )?;
+        match e.flow {
+            0 => writeln!(writer)?,
+            flow => writeln!(writer, ",{}", flow)?,
+        }
+    }
+    Ok(())
+}
+
+/// Like [`write_trace`], but returns the written trace as a string.
+pub fn format_trace(trace: &[SimEvent], client: bool) -> String {
+    let mut buf = vec![];
+    write_trace(&mut buf, trace, client).expect("writing to a Vec never fails");
+    String::from_utf8(buf).expect("the trace format is ASCII")
+}
+
+/// Write the packets seen by an observer at a [`Tap`](crate::tap::Tap), in the
+/// same format as [`write_trace`] from the viewpoint of the client: a packet
+/// going toward the server is "s" and toward the client "r". Padding cannot
+/// be told apart on the wire, so all packets are written as non-padding.
+pub fn write_observed<W: Write>(writer: &mut W, observed: &[Observed]) -> io::Result<()> {
+    let Some(start) = observed.first().map(|o| o.time) else {
+        return Ok(());
+    };
+    for o in observed {
+        writeln!(
+            writer,
+            "{},{},{}",
+            o.time.duration_since(start).as_nanos(),
+            if o.outgoing { "s" } else { "r" },
+            o.size
+        )?;
+    }
+    Ok(())
+}
+
+/// Like [`write_observed`], but returns the written packets as a string.
+pub fn format_observed(observed: &[Observed]) -> String {
+    let mut buf = vec![];
+    write_observed(&mut buf, observed).expect("writing to a Vec never fails");
+    String::from_utf8(buf).expect("the trace format is ASCII")
+}
@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use maybenot_simulator::{
+    dataset::{parse_dataset, DatasetFormat},
+    format_trace,
+    network::Network,
+    parse::{ParseError, ParseErrorKind},
+    parse_trace,
+    queue::SimQueue,
+    sim, ParseArgs,
+};
+
+// simulate the queue without any machines, formatted from both sides
+fn simulated(mut sq: SimQueue, network: &Network) -> (String, String) {
+    let trace = sim(&[], &[], &mut sq, network.downlink.delay, 0, true).unwrap();
+    (format_trace(&trace, true), format_trace(&trace, false))
+}
+
+fn assert_same(dataset: &str, format: DatasetFormat, raw_trace: &str) {
+    let network = Network::new(Duration::from_millis(10));
+    let sq = parse_dataset(dataset.as_bytes(), format, &ParseArgs::new(&network)).unwrap();
+    let expected = parse_trace(raw_trace, &network).unwrap();
+    assert_eq!(simulated(sq, &network), simulated(expected, &network));
+}
+
+#[test_log::test]
+fn test_dataset_direction() {
+    assert_same(
+        "0.0\t1\n0.019714282\t-1\n0.1\t1\n0.3\t-1\n",
+        DatasetFormat::Direction { size: 1500 },
+        "0,s,1500\n19714282,r,1500\n100000000,s,1500\n300000000,r,1500\n",
+    );
+}
+
+#[test_log::test]
+fn test_dataset_tor_cells() {
+    // timestamps need not start at zero and are exact to the nanosecond,
+    // regardless of the precision of a float
+    assert_same(
+        "1700000000.5 1\n1700000000.519714282 -514\n\n1700000001.000000001 514\n",
+        DatasetFormat::TorCells,
+        "0,s,514\n19714282,r,514\n500000001,s,514\n",
+    );
+}
+
+#[test_log::test]
+fn test_dataset_signed_size() {
+    assert_same(
+        "0\t52\n.019714282\t-1420\n1e-1\t73\n",
+        DatasetFormat::SignedSize,
+        "0,s,52\n19714282,r,1420\n100000000,s,73\n",
+    );
+}
+
+#[test_log::test]
+fn test_dataset_errors() {
+    let network = Network::new(Duration::from_millis(10));
+    let args = ParseArgs::new(&network);
+    let err = |dataset: &str, format| parse_dataset(dataset.as_bytes(), format, &args).unwrap_err();
+
+    assert_eq!(
+        err("0.0\t1\n0.1\t0\n", DatasetFormat::Direction { size: 514 }),
+        ParseError {
+            line: 2,
+            kind: ParseErrorKind::InvalidDirection("0".to_string())
+        }
+    );
+    assert_eq!(
+        err("0.0\t1\n0.1\t-70000\n", DatasetFormat::SignedSize),
+        ParseError {
+            line: 2,
+            kind: ParseErrorKind::InvalidSize("-70000".to_string())
+        }
+    );
+    assert_eq!(
+        err("1.0\t1\n0.5\t1\n", DatasetFormat::TorCells),
+        ParseError {
+            line: 2,
+            kind: ParseErrorKind::InvalidTimestamp("0.5".to_string())
+        }
+    );
+    // too late to simulate, reported as the offset in nanoseconds
+    assert_eq!(
+        err("0\t1\n99999999999\t1\n", DatasetFormat::TorCells),
+        ParseError {
+            line: 2,
+            kind: ParseErrorKind::InvalidTimestamp("99999999999000000000".to_string())
+        }
+    );
+    assert_eq!(
+        err("0.0\t1\t514\n", DatasetFormat::TorCells),
+        ParseError {
+            line: 1,
+            kind: ParseErrorKind::InvalidFormat("0.0\t1\t514".to_string())
+        }
+    );
+}
@@ -20,14 +20,15 @@ fn simulator_example_use() {
     9420892765,r,191";
 
     // The network model for simulating the network between the client and the
-    // server. Currently just a delay.
-    let network = Network::new(Duration::from_millis(10));
+    // server. Here just a delay, but the bandwidth can also be limited.
+    let delay = Duration::from_millis(10);
+    let network = Network::new(delay);
 
     // Parse the raw trace into a queue of events for the simulator. This uses
     // the delay to generate a queue of events at the client and server in such
     // a way that the client is ensured to get the packets in the same order and
     // at the same time as in the raw trace.
-    let mut input_trace = parse_trace(raw_trace, &network);
+    let mut input_trace = parse_trace(raw_trace, &network).unwrap();
 
     // A simple machine that sends one padding packet of 1000 bytes 20
     // milliseconds after the first NonPaddingSent is sent.
@@ -37,40 +38,32 @@ fn simulator_example_use() {
 
     // Run the simulator with the machine at the client. Run the simulation up
     // until 100 packets have been recorded (total, client and server).
-    let trace = sim(&[m], &[], &mut input_trace, network.delay, 100, true);
+    let trace = sim(&[m], &[], &mut input_trace, delay, 100, true).unwrap();
 
-    // print packets from the client's perspective
-    let starting_time = trace[0].time;
+    // print packets from the client's perspective, with times since the start
+    // of the trace
     trace
         .into_iter()
         .filter(|p| p.client)
         .for_each(|p| match p.event {
             TriggerEvent::NonPaddingSent { bytes_sent } => {
-                println!(
-                    "sent {} bytes at {} ms",
-                    bytes_sent,
-                    (p.time - starting_time).as_millis()
-                );
+                println!("sent {} bytes at {} ms", bytes_sent, p.time.as_millis());
             }
             TriggerEvent::PaddingSent { bytes_sent, .. } => {
                 println!(
                     "sent {} bytes of padding at {} ms",
                     bytes_sent,
-                    (p.time - starting_time).as_millis()
+                    p.time.as_millis()
                 );
             }
             TriggerEvent::NonPaddingRecv { bytes_recv } => {
-                println!(
-                    "received {} bytes at {} ms",
-                    bytes_recv,
-                    (p.time - starting_time).as_millis()
-                );
+                println!("received {} bytes at {} ms", bytes_recv, p.time.as_millis());
             }
             TriggerEvent::PaddingRecv { bytes_recv, .. } => {
                 println!(
                     "received {} bytes of padding at {} ms",
                     bytes_recv,
-                    (p.time - starting_time).as_millis()
+                    p.time.as_millis()
                 );
             }
             _ => {}
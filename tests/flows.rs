@@ -0,0 +1,93 @@
+use std::{str::FromStr, time::Duration};
+
+use maybenot::{framework::TriggerEvent, machine::Machine};
+use maybenot_simulator::{
+    format_trace, network::Network, parse_trace, parse_trace_advanced, sim_advanced, ParseArgs,
+    SimEvent, Simulator, SimulatorArgs,
+};
+
+// sends one padding packet of 1000 bytes 20 ms after the first non-padding
+// sent, as in the example in the crate documentation
+fn padding_machine() -> Machine {
+    let m = "789cedcfc10900200805506d82b6688c1caf5bc3b54823f4a1a2a453b7021ff8ff49\
+    41261f685323426187f8d3f9cceb18039205b9facab8914adf9d6d9406142f07f0";
+    Machine::from_str(m).unwrap()
+}
+
+// the time (ms) and flow of the padding sent by the client
+fn padding(trace: &[SimEvent]) -> Vec<(i64, usize)> {
+    trace
+        .iter()
+        .filter(|e| e.client && matches!(e.event, TriggerEvent::PaddingSent { .. }))
+        .map(|e| (e.time.as_millis(), e.flow))
+        .collect()
+}
+
+#[test_log::test]
+fn test_flows_per_connection_and_host() {
+    // two connections, the second opened 5 ms after the first
+    let raw_trace = "0,s,52\n5000000,s,52,1\n";
+    let network = Network::new(Duration::from_millis(10));
+    let machines = [padding_machine()];
+    let args = SimulatorArgs::new(&network, 0, true);
+
+    // per connection: each flow runs its own instance of the machine
+    let mut sq = parse_trace(raw_trace, &network).unwrap();
+    let trace = sim_advanced(&machines, &[], &mut sq, &args).unwrap();
+    assert_eq!(padding(&trace), vec![(20, 0), (25, 1)]);
+
+    // per host: one instance of the machine for both connections
+    let mut parse_args = ParseArgs::new(&network);
+    parse_args.single_flow = true;
+    let mut sq = parse_trace_advanced(raw_trace, &parse_args).unwrap();
+    let trace = sim_advanced(&machines, &[], &mut sq, &args).unwrap();
+    assert_eq!(padding(&trace), vec![(20, 0)]);
+
+    // different machines per flow: only the second flow is defended
+    let sq = parse_trace(raw_trace, &network).unwrap();
+    let per_flow: [(&[Machine], &[Machine]); 2] = [(&[], &[]), (&machines, &[])];
+    let simulator = Simulator::new_per_flow(&per_flow, sq, &args).unwrap();
+    assert_eq!(simulator.flows().collect::<Vec<_>>(), vec![0, 1]);
+    let trace: Vec<SimEvent> = simulator.into_trace().collect::<Result<_, _>>().unwrap();
+    assert_eq!(padding(&trace), vec![(25, 1)]);
+}
+
+#[test_log::test]
+fn test_flows_sparse_ids() {
+    // only the flows in the trace are simulated, keeping their ids
+    let raw_trace = "0,s,52\n5000000,s,52,5000000\n";
+    let network = Network::new(Duration::from_millis(10));
+    let machines = [padding_machine()];
+    let args = SimulatorArgs::new(&network, 0, true);
+
+    let sq = parse_trace(raw_trace, &network).unwrap();
+    let simulator = Simulator::new(&machines, &[], sq, &args).unwrap();
+    assert_eq!(simulator.flows().collect::<Vec<_>>(), vec![0, 5000000]);
+    assert!(simulator.client(5000000).scheduled_actions().is_empty());
+    let trace: Vec<SimEvent> = simulator.into_trace().collect::<Result<_, _>>().unwrap();
+    assert_eq!(padding(&trace), vec![(20, 0), (25, 5000000)]);
+    assert_eq!(
+        format_trace(&trace, true),
+        "0,s,52\n5000000,s,52,5000000\n20000000,sp,1000\n25000000,sp,1000,5000000\n"
+    );
+}
+
+#[test_log::test]
+fn test_flows_share_network() {
+    // two flows sending at once over an 8 Mbps uplink: 1000 bytes take 1 ms
+    let raw_trace = "0,s,1000\n0,s,1000,1\n";
+    let mut network = Network::new(Duration::from_millis(10));
+    network.uplink.bandwidth = Some(8_000_000);
+    let mut args = SimulatorArgs::new(&network, 0, true);
+
+    // each flow has its own link
+    let mut sq = parse_trace(raw_trace, &network).unwrap();
+    let trace = sim_advanced(&[], &[], &mut sq, &args).unwrap();
+    assert_eq!(format_trace(&trace, false), "0,r,1000\n0,r,1000,1\n");
+
+    // the second packet queues up behind the first on the shared link
+    args.share_network = true;
+    let mut sq = parse_trace(raw_trace, &network).unwrap();
+    let trace = sim_advanced(&[], &[], &mut sq, &args).unwrap();
+    assert_eq!(format_trace(&trace, false), "0,r,1000\n1000000,r,1000,1\n");
+}
@@ -3,13 +3,14 @@ use std::{collections::HashMap, time::Duration};
 use maybenot::{
     dist::{Dist, DistType},
     event::Event,
+    framework::TriggerEvent,
     machine::Machine,
     state::State,
 };
 use maybenot_simulator::{
     integration::{BinDist, Integration},
-    network::Network,
-    parse_trace_advanced, sim_advanced, SimEvent, SimulatorArgs,
+    network::{DelayDist, LossModel, Network},
+    parse_trace_advanced, sim_advanced, ParseArgs, SimEvent, Simulator, SimulatorArgs,
 };
 
 fn get_test_machine() -> Machine {
@@ -55,12 +56,15 @@ fn run_sim(
         100000000,s,100";
     let network = Network::new(Duration::from_millis(5));
 
-    let mut input_trace = parse_trace_advanced(raw_trace, &network, client, server);
+    let mut parse_args = ParseArgs::new(&network);
+    parse_args.client_integration = client;
+    parse_args.server_integration = server;
+    let mut input_trace = parse_trace_advanced(raw_trace, &parse_args).unwrap();
 
     let mut args = SimulatorArgs::new(&network, 100, true);
     args.client_integration = client;
     args.server_integration = server;
-    let trace = sim_advanced(&[m], &[], &mut input_trace, &args);
+    let trace = sim_advanced(&[m], &[], &mut input_trace, &args).unwrap();
 
     let trace: Vec<_> = trace
         .into_iter()
@@ -331,3 +335,125 @@ fn test_action_reporting_and_delay() {
         );
     }
 }
+
+#[test_log::test]
+fn test_seeded_runs_are_reproducible() {
+    // random delays everywhere the simulator samples: integration delays,
+    // jitter, and packet loss
+    let delay = BinDist::new(r#"{"(0.0, 2.0)": 0.5, "(2.0, 4.0)": 0.5}"#).unwrap();
+    let integration = Integration {
+        action_delay: delay.clone(),
+        reporting_delay: delay.clone(),
+        trigger_delay: delay,
+    };
+    let mut network = Network::new(Duration::from_millis(5));
+    network.uplink.jitter = Some(DelayDist::Normal {
+        mean: 1.0,
+        std_dev: 1.0,
+    });
+    network.downlink.loss = Some(LossModel::Bernoulli { p: 0.3 });
+    let raw_trace = (0..50)
+        .map(|i| format!("{},{},100", i * 1000000, if i % 2 == 0 { "s" } else { "r" }))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let machines = [get_test_machine()];
+    let simulator = |seed: u64| {
+        // reporting delays are sampled when parsing
+        let mut parse_args = ParseArgs::new(&network);
+        parse_args.client_integration = Some(&integration);
+        parse_args.server_integration = Some(&integration);
+        parse_args.seed = Some(seed);
+        let input_trace = parse_trace_advanced(&raw_trace, &parse_args).unwrap();
+        let mut args = SimulatorArgs::new(&network, 1000, false);
+        args.client_integration = Some(&integration);
+        args.server_integration = Some(&integration);
+        args.seed = Some(seed);
+        Simulator::new(&machines, &[], input_trace, &args).unwrap()
+    };
+    let run = |seed: u64| {
+        let mut simulator = simulator(seed);
+        let mut trace = vec![];
+        while let Some(event) = simulator.step().unwrap() {
+            trace.push(event);
+        }
+        trace
+    };
+
+    assert_eq!(run(1), run(1));
+    assert_eq!(run(2), run(2));
+    assert_ne!(run(1), run(2));
+
+    // each simulator has its own RNG: interleaving two runs on the same
+    // thread gives the same traces as running them one at a time
+    let (mut first, mut second) = (simulator(1), simulator(2));
+    let (mut first_trace, mut second_trace) = (vec![], vec![]);
+    loop {
+        let a = first.step().unwrap();
+        let b = second.step().unwrap();
+        if a.is_none() && b.is_none() {
+            break;
+        }
+        first_trace.extend(a);
+        second_trace.extend(b);
+    }
+    assert_eq!(first_trace, run(1));
+    assert_eq!(second_trace, run(2));
+}
+
+#[test_log::test]
+fn test_seeded_runs_with_coinciding_actions() {
+    // two machines that pad at the same time: which pads first must not
+    // change between runs
+    let machines = [get_test_machine(), get_test_machine()];
+    let network = Network::new(Duration::from_millis(5));
+    let run = || {
+        let mut parse_args = ParseArgs::new(&network);
+        parse_args.seed = Some(0);
+        let mut input_trace = parse_trace_advanced("0,s,100\n", &parse_args).unwrap();
+        let mut args = SimulatorArgs::new(&network, 0, true);
+        args.seed = Some(0);
+        sim_advanced(&machines, &[], &mut input_trace, &args).unwrap()
+    };
+
+    let trace = run();
+    let padding = trace
+        .iter()
+        .filter(|e| matches!(e.event, TriggerEvent::PaddingSent { .. }))
+        .count();
+    assert_eq!(padding, 2);
+    for _ in 0..20 {
+        assert_eq!(run(), trace);
+    }
+}
+
+#[test_log::test]
+fn test_trace_iterator_in_time_order() {
+    // with integration delays, events are not simulated in time order
+    let delay = BinDist::new(r#"{"(0.0, 2.0)": 0.5, "(2.0, 4.0)": 0.5}"#).unwrap();
+    let integration = Integration {
+        action_delay: delay.clone(),
+        reporting_delay: delay.clone(),
+        trigger_delay: delay,
+    };
+    let network = Network::new(Duration::from_millis(5));
+    let raw_trace = (0..50)
+        .map(|i| format!("{},{},100", i * 1000000, if i % 3 == 0 { "s" } else { "r" }))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut parse_args = ParseArgs::new(&network);
+    parse_args.client_integration = Some(&integration);
+    parse_args.server_integration = Some(&integration);
+    let input_trace = parse_trace_advanced(&raw_trace, &parse_args).unwrap();
+    let mut args = SimulatorArgs::new(&network, 0, false);
+    args.client_integration = Some(&integration);
+    args.server_integration = Some(&integration);
+    args.seed = Some(0);
+    let machines = [get_test_machine()];
+
+    // yields the same events as the sorted trace from sim_advanced
+    let expected = sim_advanced(&machines, &[], &mut input_trace.clone(), &args).unwrap();
+    let simulator = Simulator::new(&machines, &[], input_trace, &args).unwrap();
+    let trace: Vec<SimEvent> = simulator.into_trace().collect::<Result<_, _>>().unwrap();
+    assert_eq!(trace, expected);
+}
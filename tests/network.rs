@@ -0,0 +1,565 @@
+use std::{str::FromStr, time::Duration};
+
+use maybenot::{event::Event, framework::TriggerEvent, machine::Machine};
+use maybenot_simulator::{
+    integration::{BinDist, Integration},
+    network::{
+        Buffer, BufferCapacity, DelayDist, DropPolicy, Link, LinkSample, LinkTrace, LossModel,
+        Network, NetworkModel, NetworkPacket,
+    },
+    parse_trace, sim_advanced,
+    time::SimTime,
+    SimEvent, SimulatorArgs,
+};
+
+fn run_sim(raw_trace: &str, network: &Network) -> Vec<SimEvent> {
+    let mut input_trace = parse_trace(raw_trace, network).unwrap();
+    let args = SimulatorArgs::new(network, 100, true);
+    sim_advanced(&[], &[], &mut input_trace, &args).unwrap()
+}
+
+// time of all events of the given type at the client or server, relative to
+// the first event in the trace
+fn times(trace: &[SimEvent], client: bool, event: Event) -> Vec<Duration> {
+    trace
+        .iter()
+        .filter(|e| e.client == client && e.event.is_event(event))
+        .map(|e| e.time - trace[0].time)
+        .collect()
+}
+
+#[test_log::test]
+fn test_transmission_time() {
+    let mut link = Link::new(Duration::from_millis(5));
+    assert_eq!(link.transmission_time(1000), Duration::ZERO);
+
+    // 8 Mbps: 1000 bytes is 8000 bits, 1 ms
+    link.bandwidth = Some(8_000_000);
+    assert_eq!(link.transmission_time(1000), Duration::from_millis(1));
+    assert_eq!(link.transmission_time(52), Duration::from_micros(52));
+}
+
+#[test_log::test]
+fn test_bandwidth_queueing() {
+    // a burst of three packets sent by the client
+    let raw_trace = "0,s,1000
+        0,s,1000
+        100000,s,52";
+
+    // without a bandwidth limit, all packets arrive after the delay
+    let network = Network::new(Duration::from_millis(5));
+    let trace = run_sim(raw_trace, &network);
+    assert_eq!(
+        times(&trace, false, Event::NonPaddingRecv),
+        vec![
+            Duration::from_millis(5),
+            Duration::from_millis(5),
+            Duration::from_micros(5100)
+        ]
+    );
+
+    // with 8 Mbps, each 1000 byte packet takes 1 ms to transmit and the
+    // packets queue up behind each other
+    let mut network = Network::new(Duration::from_millis(5));
+    network.uplink.bandwidth = Some(8_000_000);
+    let trace = run_sim(raw_trace, &network);
+    assert_eq!(
+        times(&trace, false, Event::NonPaddingRecv),
+        vec![
+            Duration::from_millis(6),
+            Duration::from_millis(7),
+            Duration::from_micros(7052)
+        ]
+    );
+    // the client still sent the packets as in the trace
+    assert_eq!(
+        times(&trace, true, Event::NonPaddingSent),
+        vec![Duration::ZERO, Duration::ZERO, Duration::from_micros(100)]
+    );
+}
+
+#[test_log::test]
+fn test_bandwidth_integration_delays() {
+    // sends one padding packet of 1000 bytes 20 ms after the first non-padding
+    // sent, as in the example in the crate documentation
+    let m = "789cedcfc10900200805506d82b6688c1caf5bc3b54823f4a1a2a453b7021ff8ff49\
+    41261f685323426187f8d3f9cceb18039205b9facab8914adf9d6d9406142f07f0";
+    let machines = [Machine::from_str(m).unwrap()];
+    // the padding is sent 2 ms after the machine pads
+    let integration = Integration {
+        action_delay: BinDist::new(r#"{"(2.0, 2.0)": 1.0}"#).unwrap(),
+        reporting_delay: BinDist::new(r#"{"(0.0, 0.0)": 1.0}"#).unwrap(),
+        trigger_delay: BinDist::new(r#"{"(0.0, 0.0)": 1.0}"#).unwrap(),
+    };
+
+    // with 8 Mbps, 1000 bytes take 1 ms to transmit: the non-padding sent at
+    // 21 ms is simulated after the padding sent at 22 ms, but is transmitted
+    // first
+    let mut network = Network::new(Duration::from_millis(10));
+    network.uplink.bandwidth = Some(8_000_000);
+    let mut sq = parse_trace("0,s,100\n21000000,s,1000\n", &network).unwrap();
+    let mut args = SimulatorArgs::new(&network, 100, true);
+    args.max_padding_frac_client = 1.0;
+    args.client_integration = Some(&integration);
+    let trace = sim_advanced(&machines, &[], &mut sq, &args).unwrap();
+    assert_eq!(
+        times(&trace, false, Event::NonPaddingRecv),
+        vec![Duration::from_micros(10100), Duration::from_millis(32)]
+    );
+    assert_eq!(
+        times(&trace, false, Event::PaddingRecv),
+        vec![Duration::from_millis(33)]
+    );
+}
+
+#[test_log::test]
+fn test_bandwidth_received_as_in_trace() {
+    // packets received by the client should arrive as in the trace, also with
+    // a limited bandwidth, since parse_trace accounts for the transmission time
+    let raw_trace = "0,s,100
+        10000000,r,1000
+        11000000,r,1000
+        20000000,s,100";
+    let mut network = Network::new(Duration::from_millis(5));
+    network.downlink.bandwidth = Some(8_000_000);
+    let trace = run_sim(raw_trace, &network);
+
+    let recv: Vec<_> = trace
+        .iter()
+        .filter(|e| e.client && e.event.is_event(Event::NonPaddingRecv))
+        .collect();
+    assert_eq!(recv.len(), 2);
+    assert_eq!(recv[0].time - trace[0].time, Duration::from_millis(10));
+    assert_eq!(recv[1].time - trace[0].time, Duration::from_millis(11));
+    assert_eq!(
+        recv[0].event,
+        TriggerEvent::NonPaddingRecv { bytes_recv: 1000 }
+    );
+}
+
+// a trace of 50 packets sent by the client 10us apart, with sizes 100..150 to
+// tell the packets apart at the server
+fn make_burst_trace() -> String {
+    (0..50)
+        .map(|i| format!("{},s,{}\n", i * 10_000, 100 + i))
+        .collect()
+}
+
+// arrival time at the server of each packet, in the order sent
+fn arrivals_in_order(trace: &[SimEvent]) -> Vec<Duration> {
+    let mut recv: Vec<_> = trace
+        .iter()
+        .filter_map(|e| match e.event {
+            TriggerEvent::NonPaddingRecv { bytes_recv } if !e.client => {
+                Some((bytes_recv, e.time - trace[0].time))
+            }
+            _ => None,
+        })
+        .collect();
+    recv.sort_by_key(|(size, _)| *size);
+    recv.into_iter().map(|(_, t)| t).collect()
+}
+
+#[test_log::test]
+fn test_delay_dist() {
+    let bins = DelayDist::Bins(BinDist::new(r#"{"(2.0, 2.0)": 1.0}"#).unwrap());
+    assert_eq!(bins.sample(), Duration::from_millis(2));
+
+    // samples are never negative
+    let normal = DelayDist::Normal {
+        mean: -10.0,
+        std_dev: 1.0,
+    };
+    assert_eq!(normal.sample(), Duration::ZERO);
+
+    let pareto = DelayDist::Pareto {
+        scale: 1.0,
+        shape: 2.0,
+    };
+    for _ in 0..100 {
+        assert!(pareto.sample() >= Duration::from_millis(1));
+    }
+
+    let mut link = Link::new(Duration::from_millis(5));
+    link.jitter = Some(DelayDist::Bins(
+        BinDist::new(r#"{"(0.0, 10.0)": 1.0}"#).unwrap(),
+    ));
+    for _ in 0..100 {
+        let d = link.sample();
+        assert!(d >= Duration::from_millis(5) && d <= Duration::from_millis(15));
+    }
+}
+
+#[test_log::test]
+fn test_jitter_keeps_order() {
+    let raw_trace = make_burst_trace();
+    let mut network = Network::new(Duration::from_millis(5));
+    network.uplink.jitter = Some(DelayDist::Bins(
+        BinDist::new(r#"{"(0.0, 10.0)": 1.0}"#).unwrap(),
+    ));
+
+    let trace = run_sim(&raw_trace, &network);
+    let arrivals = arrivals_in_order(&trace);
+    assert_eq!(arrivals.len(), 50);
+    // no packet arrives before the one sent before it
+    assert!(arrivals.windows(2).all(|w| w[0] <= w[1]));
+    assert!(arrivals
+        .iter()
+        .all(|t| *t >= Duration::from_millis(5) && *t <= Duration::from_millis(16)));
+
+    // with reordering allowed, jitter of up to 10ms on packets sent 10us apart
+    // will (all but certainly) reorder some packets
+    network.uplink.reorder = true;
+    let trace = run_sim(&raw_trace, &network);
+    let arrivals = arrivals_in_order(&trace);
+    assert_eq!(arrivals.len(), 50);
+    assert!(!arrivals.windows(2).all(|w| w[0] <= w[1]));
+}
+
+#[test_log::test]
+fn test_asymmetric() {
+    let raw_trace = "0,s,1000
+        20000000,r,1000
+        40000000,s,1000";
+
+    // a slow uplink with a long delay and a fast downlink with a short delay
+    let mut uplink = Link::new(Duration::from_millis(10));
+    uplink.bandwidth = Some(1_000_000);
+    let mut downlink = Link::new(Duration::from_millis(2));
+    downlink.bandwidth = Some(100_000_000);
+    let network = Network::asymmetric(uplink, downlink);
+    let trace = run_sim(raw_trace, &network);
+
+    // the client receives the packet from the server as in the trace
+    assert_eq!(
+        times(&trace, true, Event::NonPaddingRecv),
+        vec![Duration::from_millis(20)]
+    );
+    // the server sent it 2ms and 80us (8000 bits at 100 Mbps) earlier
+    assert_eq!(
+        times(&trace, false, Event::NonPaddingSent),
+        vec![Duration::from_micros(17920)]
+    );
+    // packets from the client take 8ms (8000 bits at 1 Mbps) to transmit and
+    // then 10ms to arrive at the server
+    assert_eq!(
+        times(&trace, false, Event::NonPaddingRecv),
+        vec![Duration::from_millis(18), Duration::from_millis(58)]
+    );
+}
+
+fn count(trace: &[SimEvent], client: bool, event: Event) -> usize {
+    trace
+        .iter()
+        .filter(|e| e.client == client && e.event.is_event(event))
+        .count()
+}
+
+#[test_log::test]
+fn test_bernoulli_loss() {
+    let raw_trace = make_burst_trace();
+
+    // everything is lost: the client sends but the server receives nothing
+    let mut network = Network::new(Duration::from_millis(5));
+    network.uplink.loss = Some(LossModel::Bernoulli { p: 1.0 });
+    let trace = run_sim(&raw_trace, &network);
+    assert_eq!(count(&trace, true, Event::NonPaddingSent), 50);
+    assert_eq!(count(&trace, false, Event::NonPaddingRecv), 0);
+
+    // the same seed gives the same losses
+    network.uplink.loss = Some(LossModel::Bernoulli { p: 0.5 });
+    network.uplink.loss_seed = Some(1234);
+    let first = arrivals_in_order(&run_sim(&raw_trace, &network));
+    let second = arrivals_in_order(&run_sim(&raw_trace, &network));
+    assert!(first.len() < 50);
+    assert_eq!(first, second);
+
+    // with an RTO, every lost packet is eventually retransmitted and received
+    network.uplink.rto = Some(Duration::from_millis(200));
+    let mut input_trace = parse_trace(&raw_trace, &network).unwrap();
+    let args = SimulatorArgs::new(&network, 0, true);
+    let trace = sim_advanced(&[], &[], &mut input_trace, &args).unwrap();
+    assert_eq!(count(&trace, false, Event::NonPaddingRecv), 50);
+    assert!(count(&trace, true, Event::NonPaddingSent) > 50);
+    // retransmissions are sent by the client RTO after the lost packet
+    let last_sent = trace
+        .iter()
+        .filter(|e| e.client && e.event.is_event(Event::NonPaddingSent))
+        .map(|e| e.time - trace[0].time)
+        .max()
+        .unwrap();
+    assert!(last_sent >= Duration::from_millis(200));
+}
+
+#[test_log::test]
+fn test_gilbert_elliott_loss() {
+    let raw_trace = make_burst_trace();
+
+    // starts in the good state without loss, then moves to the bad state
+    // where every packet is lost and stays there
+    let mut network = Network::new(Duration::from_millis(5));
+    network.uplink.loss = Some(LossModel::GilbertElliott {
+        p: 1.0,
+        r: 0.0,
+        loss_good: 0.0,
+        loss_bad: 1.0,
+    });
+    let trace = run_sim(&raw_trace, &network);
+    assert_eq!(count(&trace, true, Event::NonPaddingSent), 50);
+    assert_eq!(count(&trace, false, Event::NonPaddingRecv), 1);
+
+    // loss on the uplink does not affect the downlink
+    let trace = run_sim("0,s,100\n1000000,r,100\n2000000,r,100", &network);
+    assert_eq!(count(&trace, true, Event::NonPaddingRecv), 2);
+}
+
+// the sizes of packets sent by the client that were dropped
+fn dropped(trace: &[SimEvent]) -> Vec<u16> {
+    trace
+        .iter()
+        .filter_map(|e| match e.event {
+            TriggerEvent::NonPaddingSent { bytes_sent } if e.client && e.dropped => {
+                Some(bytes_sent)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test_log::test]
+fn test_buffer_tail_drop() {
+    // a burst of 1000 byte packets 10us apart, then a small packet
+    let raw_trace = "0,s,1000
+        10000,s,1001
+        20000,s,1002
+        30000,s,1003
+        40000,s,52";
+
+    // 8 Mbps: 1ms per 1000 byte packet, room for two packets in the buffer
+    let mut network = Network::new(Duration::from_millis(5));
+    network.uplink.bandwidth = Some(8_000_000);
+    network.uplink.buffer = Some(Buffer {
+        capacity: BufferCapacity::Packets(2),
+        policy: DropPolicy::TailDrop,
+    });
+    let trace = run_sim(raw_trace, &network);
+    assert_eq!(dropped(&trace), vec![1002, 1003, 52]);
+    // the queueing delay is visible at the server
+    assert_eq!(
+        times(&trace, false, Event::NonPaddingRecv),
+        vec![Duration::from_millis(6), Duration::from_micros(7001)]
+    );
+
+    // room for 2500 bytes: the small packet fits
+    network.uplink.buffer = Some(Buffer {
+        capacity: BufferCapacity::Bytes(2500),
+        policy: DropPolicy::TailDrop,
+    });
+    let trace = run_sim(raw_trace, &network);
+    assert_eq!(dropped(&trace), vec![1002, 1003]);
+    assert_eq!(count(&trace, false, Event::NonPaddingRecv), 3);
+
+    // no buffer, no drops
+    network.uplink.buffer = None;
+    let trace = run_sim(raw_trace, &network);
+    assert!(dropped(&trace).is_empty());
+    assert_eq!(count(&trace, false, Event::NonPaddingRecv), 5);
+}
+
+#[test_log::test]
+fn test_buffer_red() {
+    let raw_trace = "0,s,1000
+        10000,s,1001
+        20000,s,1002
+        30000,s,1003";
+
+    // RED starts dropping early: with a weight of 1, the average is the
+    // current occupancy, and at half of the capacity every packet is dropped
+    let mut network = Network::new(Duration::from_millis(5));
+    network.uplink.bandwidth = Some(8_000_000);
+    network.uplink.buffer = Some(Buffer {
+        capacity: BufferCapacity::Packets(4),
+        policy: DropPolicy::Red {
+            min_threshold: 0.25,
+            max_threshold: 0.5,
+            max_p: 1.0,
+            weight: 1.0,
+        },
+    });
+    let trace = run_sim(raw_trace, &network);
+    assert_eq!(dropped(&trace), vec![1002, 1003]);
+
+    // tail drop would have queued all of them
+    network.uplink.buffer = Some(Buffer {
+        capacity: BufferCapacity::Packets(4),
+        policy: DropPolicy::TailDrop,
+    });
+    let trace = run_sim(raw_trace, &network);
+    assert!(dropped(&trace).is_empty());
+}
+
+#[test_log::test]
+fn test_link_trace() {
+    let trace = LinkTrace::parse(
+        "# time,rtt,bandwidth
+        0,10,8000000
+        100,40,800000",
+    )
+    .unwrap();
+    let (sample, end) = trace.at(Duration::from_millis(50));
+    assert_eq!(sample.delay, Duration::from_millis(5));
+    assert_eq!(sample.bandwidth, Some(8_000_000));
+    assert_eq!(end, Some(Duration::from_millis(100)));
+    let (sample, end) = trace.at(Duration::from_millis(150));
+    assert_eq!(sample.delay, Duration::from_millis(20));
+    assert_eq!(end, None);
+
+    // 4000 bits at 8 Mbps before the change, the remaining 4000 bits at
+    // 800 kbps after
+    assert_eq!(
+        trace.transmitted(Duration::from_micros(99500), 1000),
+        Some(Duration::from_millis(105))
+    );
+
+    // no bandwidth at first: packets wait for bandwidth
+    let sample = |ms, bandwidth| LinkSample {
+        time: Duration::from_millis(ms),
+        delay: Duration::ZERO,
+        bandwidth,
+    };
+    let trace = LinkTrace::new(vec![sample(0, Some(0)), sample(10, Some(8_000_000))]).unwrap();
+    assert_eq!(
+        trace.transmitted(Duration::ZERO, 1000),
+        Some(Duration::from_millis(11))
+    );
+    // never any bandwidth, also when repeating
+    let mut trace = LinkTrace::new(vec![sample(0, Some(0)), sample(10, Some(0))]).unwrap();
+    assert_eq!(trace.transmitted(Duration::ZERO, 1000), None);
+    trace.period = Some(Duration::from_millis(20));
+    assert_eq!(trace.transmitted(Duration::ZERO, 1000), None);
+
+    assert!(LinkTrace::parse("0,10").is_err());
+    assert!(LinkTrace::parse("").is_err());
+    assert!(LinkTrace::parse("0,-10,100").is_err());
+    assert!(LinkTrace::parse("0,NaN,100").is_err());
+    assert!(LinkTrace::parse("0,inf,100").is_err());
+    assert!(LinkTrace::parse("inf,10,100").is_err());
+}
+
+#[test_log::test]
+fn test_mahimahi_trace() {
+    // one delivery opportunity every ms: 12 Mbps, repeating after 10ms
+    let mahimahi: String = (1..=10).map(|ms| format!("{}\n", ms)).collect();
+    let trace = LinkTrace::from_mahimahi(
+        &mahimahi,
+        Duration::from_millis(10),
+        Duration::from_millis(5),
+    )
+    .unwrap();
+    assert_eq!(trace.period, Some(Duration::from_millis(10)));
+    let (sample, _) = trace.at(Duration::from_millis(3));
+    assert_eq!(sample.bandwidth, Some(12_000_000));
+    assert_eq!(sample.delay, Duration::from_millis(5));
+    // repeats
+    let (sample, end) = trace.at(Duration::from_millis(25));
+    assert_eq!(sample.bandwidth, Some(12_000_000));
+    assert_eq!(end, Some(Duration::from_millis(30)));
+
+    // the last timestamp is where the trace repeats, so it counts towards
+    // the first window
+    let trace = LinkTrace::from_mahimahi(
+        "12\n12\n12\n12\n20",
+        Duration::from_millis(10),
+        Duration::ZERO,
+    )
+    .unwrap();
+    assert_eq!(trace.samples()[0].bandwidth, Some(1_200_000));
+    assert_eq!(trace.samples()[1].bandwidth, Some(4_800_000));
+    // 12000 bits in the first window, the remaining 12000 bits at 4.8 Mbps
+    assert_eq!(
+        trace.transmitted(Duration::ZERO, 3000),
+        Some(Duration::from_micros(12500))
+    );
+
+    assert!(LinkTrace::from_mahimahi("1\nfoo", Duration::from_millis(10), Duration::ZERO).is_err());
+}
+
+#[test_log::test]
+fn test_link_trace_simulation() {
+    let raw_trace = "0,s,100
+        200000000,s,100";
+
+    // the delay goes from 5ms to 20ms after 100ms
+    let mut network = Network::new(Duration::from_millis(5));
+    network.uplink.trace = Some(
+        LinkTrace::parse(
+            "0,10,1000000000
+            100,40,1000000000",
+        )
+        .unwrap(),
+    );
+    let trace = run_sim(raw_trace, &network);
+    // 100 bytes at 1 Gbps take 800ns
+    assert_eq!(
+        times(&trace, false, Event::NonPaddingRecv),
+        vec![
+            Duration::from_nanos(5_000_800),
+            Duration::from_nanos(220_000_800)
+        ]
+    );
+}
+
+// a custom network model: packets take 1ms, except every third packet that
+// takes an extra ms (state that must not carry over between runs)
+#[derive(Debug, Clone)]
+struct SlowThirdModel {
+    sent: usize,
+}
+
+impl NetworkModel for SlowThirdModel {
+    fn new_run(&self) -> Box<dyn NetworkModel> {
+        Box::new(self.clone())
+    }
+
+    fn deliver(&mut self, packet: &NetworkPacket) -> Option<SimTime> {
+        self.sent += 1;
+        if self.sent.is_multiple_of(3) {
+            Some(packet.sent + Duration::from_millis(2))
+        } else {
+            Some(packet.sent + Duration::from_millis(1))
+        }
+    }
+
+    fn nominal_delay(&self, _client: bool, _time: Duration, _size: u16) -> Duration {
+        Duration::from_millis(1)
+    }
+}
+
+#[test_log::test]
+fn test_custom_network_model() {
+    let raw_trace = "0,s,100
+        10000000,r,100
+        20000000,s,100
+        30000000,s,100";
+    let model = SlowThirdModel { sent: 0 };
+
+    for _ in 0..2 {
+        let mut input_trace = parse_trace(raw_trace, &model).unwrap();
+        let args = SimulatorArgs::new(&model, 100, true);
+        let trace = sim_advanced(&[], &[], &mut input_trace, &args).unwrap();
+        // the server sent its packet 1ms before the client got it
+        assert_eq!(
+            times(&trace, false, Event::NonPaddingSent),
+            vec![Duration::from_millis(9)]
+        );
+        // the third packet sent (the client's second) takes an extra ms
+        assert_eq!(
+            times(&trace, false, Event::NonPaddingRecv),
+            vec![
+                Duration::from_millis(1),
+                Duration::from_millis(22),
+                Duration::from_millis(31)
+            ]
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use maybenot::{framework::TriggerEvent, machine::Machine};
+use maybenot_simulator::{
+    format_trace,
+    network::Network,
+    parse::{ParseError, ParseErrorKind},
+    parse_trace, parse_trace_advanced, parse_trace_reader, sim, sim_advanced, ParseArgs,
+    SimulatorArgs,
+};
+use std::time::Duration;
+use std::{
+    io::{BufReader, ErrorKind},
+    str::FromStr,
+};
+
+fn parse_err(raw_trace: &str) -> ParseError {
+    let network = Network::new(Duration::from_millis(10));
+    parse_trace(raw_trace, &network).unwrap_err()
+}
+
+#[test_log::test]
+fn test_parse_ok() {
+    let network = Network::new(Duration::from_millis(10));
+    // empty lines and surrounding whitespace are fine
+    let raw_trace = "0,s,52\n\n  19714282 , r , 52  \n183976147,sp,52\n";
+    let sq = parse_trace(raw_trace, &network).unwrap();
+    assert_eq!(sq.len(), 2);
+}
+
+#[test_log::test]
+fn test_parse_errors() {
+    assert_eq!(
+        parse_err("0,s,52\n19714282,r\n"),
+        ParseError {
+            line: 2,
+            kind: ParseErrorKind::InvalidFormat("19714282,r".to_string())
+        }
+    );
+    assert_eq!(
+        parse_err("0,s,52\n0,s,52,1,2\n"),
+        ParseError {
+            line: 2,
+            kind: ParseErrorKind::InvalidFormat("0,s,52,1,2".to_string())
+        }
+    );
+    assert_eq!(
+        parse_err("0,s,52\n0,s,52,-1\n"),
+        ParseError {
+            line: 2,
+            kind: ParseErrorKind::InvalidFlow("-1".to_string())
+        }
+    );
+    assert_eq!(
+        parse_err("-1,s,52\n"),
+        ParseError {
+            line: 1,
+            kind: ParseErrorKind::InvalidTimestamp("-1".to_string())
+        }
+    );
+    assert_eq!(
+        parse_err("0,s,52\n18446744073709551615,s,52\n"),
+        ParseError {
+            line: 2,
+            kind: ParseErrorKind::InvalidTimestamp("18446744073709551615".to_string())
+        }
+    );
+    assert_eq!(
+        parse_err("0,s,52\n\n100,s,65536\n"),
+        ParseError {
+            line: 3,
+            kind: ParseErrorKind::InvalidSize("65536".to_string())
+        }
+    );
+    assert_eq!(
+        parse_err("0,s,52\n100,x,52\n"),
+        ParseError {
+            line: 2,
+            kind: ParseErrorKind::InvalidDirection("x".to_string())
+        }
+    );
+
+    // the arrival of the last packet is beyond the end of simulated time
+    let network = Network::new(Duration::from_millis(10));
+    let mut args = ParseArgs::new(&network);
+    args.causal = true;
+    assert_eq!(
+        parse_trace_advanced("0,s,52\n9223372036854775807,s,52\n", &args).unwrap_err(),
+        ParseError {
+            line: 2,
+            kind: ParseErrorKind::InvalidTimestamp("9223372036854775807".to_string())
+        }
+    );
+}
+
+#[test_log::test]
+fn test_parse_error_display() {
+    assert_eq!(
+        parse_err("0,s,52\n100,x,52\n").to_string(),
+        "line 2: invalid direction \"x\""
+    );
+}
+
+#[test_log::test]
+fn test_format_trace_round_trip() {
+    let raw_trace = "0,s,52\n19714282,r,52\n183976147,s,52\n243699564,r,52\n";
+    let network = Network::new(Duration::from_millis(10));
+
+    // without machines, the client's trace is the input trace
+    let mut sq = parse_trace(raw_trace, &network).unwrap();
+    let trace = sim(&[], &[], &mut sq, network.downlink.delay, 100, true).unwrap();
+    assert_eq!(format_trace(&trace, true), raw_trace);
+    // at the server, times are relative to when it sent its first packet
+    // (10ms before the client received it)
+    assert_eq!(
+        format_trace(&trace, false),
+        "0,s,52\n285718,r,52\n184261865,r,52\n223985282,s,52\n"
+    );
+
+    // padding is written as sp/rp: this machine sends one padding packet of
+    // 1000 bytes 20 milliseconds after the first NonPaddingSent is sent
+    let m = "789cedcfc10900200805506d82b6688c1caf5bc3b54823f4a1a2a453b7021ff8ff49\
+    41261f685323426187f8d3f9cceb18039205b9facab8914adf9d6d9406142f07f0";
+    let m = Machine::from_str(m).unwrap();
+    let mut sq = parse_trace(raw_trace, &network).unwrap();
+    let trace = sim(&[m], &[], &mut sq, network.downlink.delay, 100, true).unwrap();
+    let client = format_trace(&trace, true);
+    assert_eq!(
+        client,
+        "0,s,52\n19714282,r,52\n20000000,sp,1000\n183976147,s,52\n243699564,r,52\n"
+    );
+    assert!(format_trace(&trace, false).contains("20285718,rp,1000\n"));
+
+    // and the written trace can be parsed again
+    let sq = parse_trace(&client, &network).unwrap();
+    assert_eq!(sq.len(), 4);
+}
+
+#[test_log::test]
+fn test_parse_padding() {
+    let raw_trace = "0,s,52\n1000000,sp,1000\n19714282,r,52\n20000000,rp,1000\n";
+    let network = Network::new(Duration::from_millis(10));
+
+    // ignored by default
+    let sq = parse_trace(raw_trace, &network).unwrap();
+    assert_eq!(sq.len(), 2);
+
+    // or kept as padding, reported as padding in the simulated trace
+    let mut args = ParseArgs::new(&network);
+    args.padding = true;
+    let mut sq = parse_trace_advanced(raw_trace, &args).unwrap();
+    assert_eq!(sq.len(), 4);
+    let trace = sim(&[], &[], &mut sq, network.downlink.delay, 100, true).unwrap();
+    assert_eq!(format_trace(&trace, true), raw_trace);
+    assert_eq!(
+        trace
+            .iter()
+            .filter(|e| !e.client && matches!(e.event, TriggerEvent::PaddingRecv { .. }))
+            .count(),
+        1
+    );
+
+    // the padding takes up capacity on the link: at 1 Mbps, 1000 bytes of
+    // padding take 8ms to transmit, delaying the packet sent right after it
+    let raw_trace = "0,sp,1000\n1000,s,52\n";
+    let mut network = Network::new(Duration::from_millis(10));
+    network.uplink.bandwidth = Some(1_000_000);
+    let mut args = ParseArgs::new(&network);
+    args.padding = true;
+    let mut sq = parse_trace_advanced(raw_trace, &args).unwrap();
+    let sim_args = SimulatorArgs::new(&network, 100, true);
+    let trace = sim_advanced(&[], &[], &mut sq, &sim_args).unwrap();
+    assert_eq!(format_trace(&trace, false), "0,rp,1000\n416000,r,52\n");
+    let recv = trace
+        .iter()
+        .find(|e| !e.client && matches!(e.event, TriggerEvent::NonPaddingRecv { .. }))
+        .unwrap();
+    // 8ms for the padding, then 0.416ms for the packet, plus the delay
+    assert_eq!(recv.time.as_nanos(), 18_416_000);
+}
+
+#[test_log::test]
+fn test_parse_trace_reader() {
+    let raw_trace = "0,s,52\r\n19714282,r,52\r\n\r\n183976147,s,52\r\n243699564,r,52";
+    let network = Network::new(Duration::from_millis(10));
+    let args = ParseArgs::new(&network);
+
+    // the same as parsing from memory
+    let mut sq = parse_trace_reader(BufReader::new(raw_trace.as_bytes()), &args).unwrap();
+    let trace = sim(&[], &[], &mut sq, network.downlink.delay, 100, true).unwrap();
+    let mut expected = parse_trace(raw_trace, &network).unwrap();
+    let expected = sim(&[], &[], &mut expected, network.downlink.delay, 100, true).unwrap();
+    assert_eq!(format_trace(&trace, true), format_trace(&expected, true));
+    assert_eq!(format_trace(&trace, false), format_trace(&expected, false));
+
+    // errors on the right line
+    let err = parse_trace_reader("0,s,52\n\n1,x,52\n".as_bytes(), &args).unwrap_err();
+    assert_eq!(
+        err,
+        ParseError {
+            line: 3,
+            kind: ParseErrorKind::InvalidDirection("x".to_string())
+        }
+    );
+    let err = parse_trace_reader(&b"0,s,52\n1,s,\xff\n"[..], &args).unwrap_err();
+    assert_eq!(
+        err,
+        ParseError {
+            line: 2,
+            kind: ParseErrorKind::Io(ErrorKind::InvalidData)
+        }
+    );
+}
@@ -0,0 +1,182 @@
+use std::{borrow::Cow, net::IpAddr, time::Duration};
+
+use maybenot_simulator::{
+    format_trace,
+    network::Network,
+    parse_trace,
+    pcap::{parse_pcap, PcapClient, PcapError},
+    queue::SimQueue,
+    sim, ParseArgs,
+};
+use pcap_file::{
+    pcap::{PcapHeader, PcapPacket, PcapWriter},
+    pcapng::{
+        blocks::{
+            enhanced_packet::EnhancedPacketBlock, interface_description::InterfaceDescriptionBlock,
+        },
+        PcapNgBlock, PcapNgWriter,
+    },
+    DataLink,
+};
+
+fn ipv4_udp(src: [u8; 4], dst: [u8; 4], ports: (u16, u16), size: u16) -> Vec<u8> {
+    let mut p = vec![0u8; size as usize];
+    p[0] = 0x45;
+    p[2..4].copy_from_slice(&size.to_be_bytes());
+    p[9] = 17;
+    p[12..16].copy_from_slice(&src);
+    p[16..20].copy_from_slice(&dst);
+    p[20..22].copy_from_slice(&ports.0.to_be_bytes());
+    p[22..24].copy_from_slice(&ports.1.to_be_bytes());
+    p
+}
+
+fn ipv6_tcp(src: u8, dst: u8, ports: (u16, u16), size: u16) -> Vec<u8> {
+    let mut p = vec![0u8; size as usize];
+    p[0] = 0x60;
+    p[4..6].copy_from_slice(&(size - 40).to_be_bytes());
+    p[6] = 6;
+    p[23] = src;
+    p[39] = dst;
+    p[40..42].copy_from_slice(&ports.0.to_be_bytes());
+    p[42..44].copy_from_slice(&ports.1.to_be_bytes());
+    p
+}
+
+fn ethernet(ethertype: u16, payload: &[u8], vlan: bool) -> Vec<u8> {
+    let mut f = vec![0u8; 12];
+    if vlan {
+        f.extend_from_slice(&0x8100u16.to_be_bytes());
+        f.extend_from_slice(&[0, 42]);
+    }
+    f.extend_from_slice(&ethertype.to_be_bytes());
+    f.extend_from_slice(payload);
+    f
+}
+
+fn write_pcap(datalink: DataLink, packets: &[(Duration, Vec<u8>)]) -> Vec<u8> {
+    let header = PcapHeader {
+        datalink,
+        ..Default::default()
+    };
+    let mut writer = PcapWriter::with_header(vec![], header).unwrap();
+    for (time, data) in packets {
+        writer
+            .write_packet(&PcapPacket::new(*time, data.len() as u32, data))
+            .unwrap();
+    }
+    writer.into_writer()
+}
+
+// simulate the queue without any machines, formatted from both sides
+fn simulated(mut sq: SimQueue, network: &Network) -> (String, String) {
+    let trace = sim(&[], &[], &mut sq, network.downlink.delay, 0, true).unwrap();
+    (format_trace(&trace, true), format_trace(&trace, false))
+}
+
+#[test_log::test]
+fn test_pcap_ethernet_ipv4() {
+    let client = [10, 0, 0, 1];
+    let server = [10, 0, 0, 2];
+    let other = [10, 0, 0, 3];
+    let start = Duration::from_secs(1_700_000_000);
+    let at = |us: u64| start + Duration::from_micros(us);
+    let packets = vec![
+        // not the client
+        (
+            at(0),
+            ethernet(0x0800, &ipv4_udp(other, server, (1, 2), 100), false),
+        ),
+        (
+            at(10),
+            ethernet(0x0800, &ipv4_udp(client, server, (1, 2), 52), false),
+        ),
+        // ARP
+        (at(20), ethernet(0x0806, &[0u8; 28], false)),
+        (
+            at(19714),
+            ethernet(0x0800, &ipv4_udp(server, client, (2, 1), 52), true),
+        ),
+        (
+            at(183976),
+            ethernet(0x0800, &ipv4_udp(client, server, (1, 2), 1420), false),
+        ),
+    ];
+    let pcap = write_pcap(DataLink::ETHERNET, &packets);
+
+    let network = Network::new(Duration::from_millis(10));
+    let client = PcapClient::Addr(IpAddr::from(client));
+    let sq = parse_pcap(pcap.as_slice(), client, &ParseArgs::new(&network)).unwrap();
+    assert_eq!(sq.len(), 3);
+
+    // exactly like parsing the trace, relative to the first client packet
+    let raw_trace = "0,s,52\n19704000,r,52\n183966000,s,1420\n";
+    let expected = parse_trace(raw_trace, &network).unwrap();
+    assert_eq!(simulated(sq, &network), simulated(expected, &network));
+}
+
+#[test_log::test]
+fn test_pcapng_raw_ipv6() {
+    let interface = InterfaceDescriptionBlock {
+        linktype: DataLink::RAW,
+        snaplen: 0xffff,
+        options: vec![],
+    };
+    let packets = [
+        (1_000_000, ipv6_tcp(1, 2, (51820, 443), 72)),
+        (1_002_000, ipv6_tcp(2, 1, (443, 51820), 1280)),
+        (1_002_500, ipv6_tcp(2, 3, (443, 1234), 1280)),
+        (1_003_000, ipv6_tcp(1, 2, (51820, 443), 60)),
+    ];
+    let mut writer = PcapNgWriter::new(vec![]).unwrap();
+    writer.write_block(&interface.into_block()).unwrap();
+    for (time, data) in &packets {
+        // without an if_tsresol option, timestamps are in microseconds
+        let packet = EnhancedPacketBlock {
+            interface_id: 0,
+            timestamp: Duration::from_nanos(*time),
+            original_len: data.len() as u32,
+            data: Cow::Borrowed(data),
+            options: vec![],
+        };
+        writer.write_block(&packet.into_block()).unwrap();
+    }
+    let pcapng = writer.into_inner();
+
+    let network = Network::new(Duration::from_millis(1));
+    let args = ParseArgs::new(&network);
+    let sq = parse_pcap(pcapng.as_slice(), PcapClient::Port(51820), &args).unwrap();
+    assert_eq!(sq.len(), 3);
+    let raw_trace = "0,s,72\n2000000,r,1280\n3000000,s,60\n";
+    let expected = parse_trace(raw_trace, &network).unwrap();
+    assert_eq!(simulated(sq, &network), simulated(expected, &network));
+}
+
+#[test_log::test]
+fn test_pcap_errors() {
+    let network = Network::new(Duration::from_millis(10));
+    let args = ParseArgs::new(&network);
+
+    let err = parse_pcap(&b"0,s,52\n"[..], PcapClient::Port(1), &args).unwrap_err();
+    assert!(matches!(err, PcapError::Read(_)));
+
+    let pcap = write_pcap(DataLink::NULL, &[(Duration::ZERO, vec![0u8; 32])]);
+    let err = parse_pcap(pcap.as_slice(), PcapClient::Port(1), &args).unwrap_err();
+    assert!(matches!(err, PcapError::UnsupportedLinkType(0)));
+    assert_eq!(err.to_string(), "unsupported link type 0");
+
+    // packets out of order in the capture
+    let packet = ipv4_udp([10, 0, 0, 1], [10, 0, 0, 2], (1, 2), 52);
+    let pcap = write_pcap(
+        DataLink::RAW,
+        &[
+            (Duration::from_secs(1), packet.clone()),
+            (Duration::from_millis(500), packet),
+        ],
+    );
+    let err = parse_pcap(pcap.as_slice(), PcapClient::Port(1), &args).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "packet 2: invalid timestamp \"0.500000000\""
+    );
+}
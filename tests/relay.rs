@@ -0,0 +1,68 @@
+use std::{str::FromStr, time::Duration};
+
+use maybenot::machine::Machine;
+use maybenot_simulator::{
+    format_trace, network::Network, parse_trace, relay::Relay, sim_advanced, SimEvent,
+    SimulatorArgs,
+};
+
+// a request from the client at 0 ms, answered by the server at 20 ms, over a
+// path with a relay halfway, 5 ms from both the client and the server
+fn simulate(relay: Relay) -> Vec<SimEvent> {
+    let path = Network::new(Duration::from_millis(10));
+    let mut sq = parse_trace("0,s,100\n30000000,r,100\n", &path).unwrap();
+
+    let network = Network::new(Duration::from_millis(5));
+    let relays = [relay];
+    let mut args = SimulatorArgs::new(&network, 0, true);
+    args.relays = &relays;
+    sim_advanced(&[], &[], &mut sq, &args).unwrap()
+}
+
+#[test_log::test]
+fn test_relay_forwards_non_padding() {
+    let network = Network::new(Duration::from_millis(5));
+    let trace = simulate(Relay::new(&network));
+
+    // the client and server see the same packets as without the relay
+    assert_eq!(format_trace(&trace, true), "0,s,100\n30000000,r,100\n");
+    assert_eq!(format_trace(&trace, false), "0,r,100\n10000000,s,100\n");
+
+    // the relay receives and forwards each packet halfway
+    let relay: Vec<_> = trace
+        .iter()
+        .filter(|e| e.relay == Some(1))
+        .map(|e| (e.time.as_millis(), e.client, e.event.to_string()))
+        .collect();
+    assert_eq!(
+        relay,
+        vec![
+            (5, false, "rn,100".to_string()),
+            (5, true, "sn,100".to_string()),
+            (25, true, "rn,100".to_string()),
+            (25, false, "sn,100".to_string()),
+        ]
+    );
+}
+
+#[test_log::test]
+fn test_relay_machines_toward_client() {
+    // sends one padding packet of 1000 bytes 20 ms after the first non-padding
+    // sent, as in the example in the crate documentation
+    let m = "789cedcfc10900200805506d82b6688c1caf5bc3b54823f4a1a2a453b7021ff8ff49\
+    41261f685323426187f8d3f9cceb18039205b9facab8914adf9d6d9406142f07f0";
+    let machines = [Machine::from_str(m).unwrap()];
+
+    // the relay pads toward the client 20 ms after forwarding the response,
+    // and the padding ends at the client
+    let network = Network::new(Duration::from_millis(5));
+    let mut relay = Relay::new(&network);
+    relay.client_machines = &machines;
+    relay.max_padding_frac = 1.0;
+    let trace = simulate(relay);
+    assert_eq!(
+        format_trace(&trace, true),
+        "0,s,100\n30000000,r,100\n50000000,rp,1000\n"
+    );
+    assert_eq!(format_trace(&trace, false), "0,r,100\n10000000,s,100\n");
+}
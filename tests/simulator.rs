@@ -1,20 +1,25 @@
 use log::debug;
-use maybenot_simulator::{network::Network, parse_trace, queue::SimQueue, sim, SimEvent};
-
-use std::{
-    cmp::Reverse,
-    collections::HashMap,
-    time::{Duration, Instant},
+use maybenot_simulator::{
+    network::{LossModel, Network},
+    parse_trace, parse_trace_advanced,
+    queue::SimQueue,
+    replace::{SizeCompensating, NETWORK_REPLACE_WINDOW},
+    sim, sim_advanced,
+    time::SimTime,
+    ParseArgs, SimError, SimEvent, Simulator, SimulatorArgs,
 };
 
+use std::{cmp::Reverse, collections::HashMap, time::Duration};
+
 use maybenot::{
     dist::{Dist, DistType},
     event::Event,
-    framework::TriggerEvent,
+    framework::{Action, TriggerEvent},
     machine::Machine,
     state::State,
 };
 
+#[allow(clippy::too_many_arguments)]
 fn run_test_sim(
     input: &str,
     output: &str,
@@ -25,7 +30,7 @@ fn run_test_sim(
     max_trace_length: usize,
     only_packets: bool,
 ) {
-    let starting_time = Instant::now();
+    let starting_time = SimTime::ZERO;
     let mut sq = make_sq(input.to_string(), delay, starting_time);
     let trace = sim(
         machines_client,
@@ -34,7 +39,8 @@ fn run_test_sim(
         delay,
         max_trace_length,
         only_packets,
-    );
+    )
+    .unwrap();
     let mut fmt = fmt_trace(trace.clone(), client);
     if fmt.len() > output.len() {
         fmt = fmt.get(0..output.len()).unwrap().to_string();
@@ -44,25 +50,21 @@ fn run_test_sim(
 }
 
 fn fmt_trace(trace: Vec<SimEvent>, client: bool) -> String {
-    let base = trace[0].time.clone();
+    let base = trace[0].time;
     let mut s: String = "".to_string();
-    for i in 0..trace.len() {
-        if trace[i].client == client {
-            s = format!("{} {}", s, fmt_event(&trace[i], base));
+    for e in &trace {
+        if e.client == client {
+            s = format!("{} {}", s, fmt_event(e, base));
         }
     }
     s.trim().to_string()
 }
 
-fn fmt_event(e: &SimEvent, base: Instant) -> String {
-    format!(
-        "{:1},{}",
-        e.time.duration_since(base).as_micros(),
-        e.event.to_string()
-    )
+fn fmt_event(e: &SimEvent, base: SimTime) -> String {
+    format!("{:1},{}", e.time.duration_since(base).as_micros(), e.event)
 }
 
-fn make_sq(s: String, delay: Duration, starting_time: Instant) -> SimQueue {
+fn make_sq(s: String, delay: Duration, starting_time: SimTime) -> SimQueue {
     let mut sq = SimQueue::new();
     let integration_delay = Duration::from_micros(0);
 
@@ -172,7 +174,7 @@ fn test_simple_pad_machine() {
         "0,sn,100 18,sn,200 25,rn,300 25,rn,300 30,sn,500 35,rn,600",
         "0,sn,100 8,sp,1420 16,sp,1420 18,sn,200 24,sp,1420 25,rn,300 25,rn,300 30,sn,500 32,sp,1420 35,rn,600",
         Duration::from_micros(5),
-        &[m.clone()],
+        std::slice::from_ref(&m),
         &[],
         true,
         20,
@@ -184,7 +186,7 @@ fn test_simple_pad_machine() {
         "0,sn,100 18,sn,200 25,rn,300 25,rn,300 30,sn,500 35,rn,600",
         "5,rn,100 13,rp,1420 20,sn,300 20,sn,300 21,rp,1420 23,rn,200 29,rp,1420 30,sn,600 35,rn,500 37,rp,1420 45,rp,1420",
         Duration::from_micros(5),
-        &[m.clone()],
+        std::slice::from_ref(&m),
         &[],
         false,
         50,
@@ -197,7 +199,7 @@ fn test_simple_pad_machine() {
         "0,sn,100 18,sn,200 25,rn,300 25,rn,300 30,sn,500 33,rp,1420 35,rn,600",
         Duration::from_micros(5),
         &[],
-        &[m.clone()],
+        std::slice::from_ref(&m),
         true,
         30,
         false,
@@ -216,9 +218,8 @@ fn test_simple_pad_machine() {
     );
 }
 
-#[test_log::test]
-fn test_simple_block_machine() {
-    // a simple machine that waits for 5us, blocks for 5us, and then repeats forever
+// a simple machine that waits for 5us, blocks for 5us, and then repeats forever
+fn block_machine() -> Machine {
     let num_states = 2;
     let mut t: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
     let mut e: HashMap<usize, f64> = HashMap::new();
@@ -245,14 +246,19 @@ fn test_simple_block_machine() {
         max: 0.0,
     };
     s1.action_is_block = true;
-    let m = Machine {
+    Machine {
         allowed_padding_bytes: 0,
         max_padding_frac: 0.0,
         allowed_blocked_microsec: 1000,
         max_blocking_frac: 1.0,
         states: vec![s0, s1],
         include_small_packets: true,
-    };
+    }
+}
+
+#[test_log::test]
+fn test_simple_block_machine() {
+    let m = block_machine();
 
     // client
     // note in the output how 18,sn,200 should be delayed until 20,sn,200 due to blocking
@@ -260,7 +266,7 @@ fn test_simple_block_machine() {
         "0,sn,100 18,sn,200 25,rn,300 25,rn,300 30,sn,500 35,rn,600",
         "0,sn,100 5,bb 10,be 15,bb 20,sn,200 20,be 25,rn,300 25,rn,300 25,bb 30,sn,500 30,be 35,rn,600 35,bb",
         Duration::from_micros(5),
-        &[m.clone()],
+        std::slice::from_ref(&m),
         &[],
         true,
         100,
@@ -273,7 +279,7 @@ fn test_simple_block_machine() {
         "5,rn,100 20,sn,300 20,sn,300 23,rn,200 25,bb 30,sn,600 30,be 35,rn,500 35,bb 40,be",
         Duration::from_micros(5),
         &[],
-        &[m.clone()],
+        std::slice::from_ref(&m),
         false,
         100,
         false,
@@ -395,7 +401,7 @@ fn test_blockpadding() {
         "0,sn,100 6,rn,200 14,sn,300",
         "0,sn,100 5,bb 6,rn,200 15,sp,1420 15,sn,300 15,be 16,sp,1420 17,sp,1420",
         Duration::from_micros(5),
-        &[m.clone()],
+        std::slice::from_ref(&m),
         &[],
         true,
         20,
@@ -746,6 +752,95 @@ fn test_replace_machine() {
     );
 }
 
+#[test_log::test]
+fn test_replace_window_and_policy() {
+    // a simple machine that pads every 2us six times, with replace
+    let num_states = 2;
+    let mut t: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    let mut e: HashMap<usize, f64> = HashMap::new();
+    e.insert(1, 1.0);
+    t.insert(Event::NonPaddingSent, e);
+    let s0 = State::new(t, num_states);
+    let mut t: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    let mut e: HashMap<usize, f64> = HashMap::new();
+    e.insert(1, 1.0);
+    t.insert(Event::PaddingSent, e);
+    let mut s1 = State::new(t, num_states);
+    s1.timeout = Dist {
+        dist: DistType::Uniform,
+        param1: 2.0,
+        param2: 2.0,
+        start: 0.0,
+        max: 0.0,
+    };
+    s1.limit = Dist {
+        dist: DistType::Uniform,
+        param1: 6.0,
+        param2: 6.0,
+        start: 0.0,
+        max: 0.0,
+    };
+    s1.action = Dist {
+        dist: DistType::Uniform,
+        param1: 200.0,
+        param2: 200.0,
+        start: 0.0,
+        max: 0.0,
+    };
+    s1.replace = true;
+    let m = Machine {
+        allowed_padding_bytes: 10000,
+        max_padding_frac: 1.0,
+        allowed_blocked_microsec: 0,
+        max_blocking_frac: 0.0,
+        states: vec![s0, s1],
+        include_small_packets: true,
+    };
+
+    let input = "0,sn,100 4,sn,200 6,rn,300 6,rn,300 7,sn,500";
+    let delay = Duration::from_micros(5);
+    let network = Network::new(delay);
+    let run_input = |input: &str, args: &SimulatorArgs| {
+        let mut sq = make_sq(input.to_string(), delay, SimTime::ZERO);
+        fmt_trace(
+            sim_advanced(std::slice::from_ref(&m), &[], &mut sq, args).unwrap(),
+            true,
+        )
+    };
+    let run = |args: &SimulatorArgs| run_input(input, args);
+
+    // the default window and policy, as in test_replace_machine
+    let mut args = SimulatorArgs::new(&network, 40, true);
+    assert_eq!(args.replace_window, NETWORK_REPLACE_WINDOW);
+    assert_eq!(
+        run(&args),
+        "0,sn,100 2,sp,200 4,sn,200 6,rn,300 6,rn,300 6,sp,200 7,sn,500 8,sp,200 10,sp,200 12,sp,200"
+    );
+
+    // with a 2us window, padding is replaced by packets sent up to 2us earlier
+    args.replace_window = Duration::from_micros(2);
+    assert_eq!(
+        run(&args),
+        "0,sn,100 4,sn,200 6,rn,300 6,rn,300 7,sn,500 8,sp,200 12,sp,200"
+    );
+
+    // with a smaller queued packet, the default policy sends it as-is in
+    // place of the padding at 2us
+    let input = "0,sn,300 4,sn,150 6,rn,300 6,rn,300 7,sn,500";
+    assert_eq!(
+        run_input(input, &args),
+        "0,sn,300 2,sn,150 6,rn,300 6,rn,300 6,sp,200 7,sn,500 8,sp,200 12,sp,200"
+    );
+
+    // the size-compensating policy pads it up to the size of the padding, and
+    // then the same-sized packet replaces the padding at 4us
+    args.replace_policy = &SizeCompensating;
+    assert_eq!(
+        run_input(input, &args),
+        "0,sn,300 2,sn,200 6,rn,300 6,rn,300 6,sp,200 7,sn,500 8,sp,200 12,sp,200"
+    );
+}
+
 #[test_log::test]
 fn test_bypass_replace_machine() {
     // test a machine that uses bypass and replace to construct a client-side
@@ -958,30 +1053,54 @@ fn test_excessive_sim_delay() {
 
     // start with a reasonable 10ms delay: we should get events at the client
     let network = Network::new(Duration::from_millis(10));
-    let pq = parse_trace(EARLY_TRACE, &network);
-    let trace = sim(&[], &[], &mut pq.clone(), network.delay, 10000, true);
+    let pq = parse_trace(EARLY_TRACE, &network).unwrap();
+    let trace = sim(
+        &[],
+        &[],
+        &mut pq.clone(),
+        network.downlink.delay,
+        10000,
+        true,
+    )
+    .unwrap();
     let client_trace = trace
         .clone()
         .into_iter()
         .filter(|t| t.client)
         .collect::<Vec<_>>();
-    assert!(client_trace.len() > 0);
+    assert!(!client_trace.is_empty());
 
     // set a silly delay of 10s: this should result in zero events at the
     // client, because we hit the limit of events below before we get to the
     // first event at the client
     let network = Network::new(Duration::from_millis(10000));
-    let pq = parse_trace(EARLY_TRACE, &network);
-    let trace = sim(&[], &[], &mut pq.clone(), network.delay, 10000, true);
+    let pq = parse_trace(EARLY_TRACE, &network).unwrap();
+    let trace = sim(
+        &[],
+        &[],
+        &mut pq.clone(),
+        network.downlink.delay,
+        10000,
+        true,
+    )
+    .unwrap();
     let client_trace = trace
         .clone()
         .into_iter()
         .filter(|t| t.client)
         .collect::<Vec<_>>();
-    assert!(client_trace.len() == 0);
+    assert!(client_trace.is_empty());
 
     // increase the limit of events to 100000: this should result in all events
-    let trace = sim(&[], &[], &mut pq.clone(), network.delay, 100000, true);
+    let trace = sim(
+        &[],
+        &[],
+        &mut pq.clone(),
+        network.downlink.delay,
+        100000,
+        true,
+    )
+    .unwrap();
     let client_trace = trace
         .clone()
         .into_iter()
@@ -990,3 +1109,258 @@ fn test_excessive_sim_delay() {
     // 21574 is the number of events in EARLY_TRACE
     assert_eq!(client_trace.len(), 21574);
 }
+
+#[test_log::test]
+fn test_sim_errors() {
+    let network = Network::new(Duration::from_millis(10));
+
+    // nothing to simulate
+    let mut sq = SimQueue::new();
+    let args = SimulatorArgs::new(&network, 100, true);
+    assert!(matches!(
+        sim_advanced(&[], &[], &mut sq, &args),
+        Err(SimError::EmptyQueue)
+    ));
+
+    // invalid padding fraction at the server
+    let mut sq = parse_trace("0,s,52\n19714282,r,52", &network).unwrap();
+    let mut args = SimulatorArgs::new(&network, 100, true);
+    args.max_padding_frac_server = 1.5;
+    let err = sim_advanced(&[], &[], &mut sq, &args).unwrap_err();
+    assert!(matches!(err, SimError::Framework { client: false, .. }));
+    assert!(err
+        .to_string()
+        .starts_with("failed to create framework at server"));
+
+    // an invalid machine at the client: the only state transitions to a
+    // non-existing state
+    let mut t: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    let mut e: HashMap<usize, f64> = HashMap::new();
+    e.insert(5, 1.0);
+    t.insert(Event::NonPaddingSent, e);
+    let state = State::new(t, 1);
+    let m = Machine {
+        allowed_padding_bytes: 0,
+        max_padding_frac: 0.0,
+        allowed_blocked_microsec: 0,
+        max_blocking_frac: 0.0,
+        states: vec![state],
+        include_small_packets: false,
+    };
+    let mut sq = parse_trace("0,s,52\n19714282,r,52", &network).unwrap();
+    let args = SimulatorArgs::new(&network, 100, true);
+    assert!(matches!(
+        sim_advanced(&[m], &[], &mut sq, &args),
+        Err(SimError::Framework { client: true, .. })
+    ));
+}
+
+#[test_log::test]
+fn test_sim_time_starts_at_zero() {
+    let network = Network::new(Duration::from_millis(10));
+    let mut sq = parse_trace("0,s,52\n5000000,r,52\n30000000,r,52", &network).unwrap();
+    let trace = sim(&[], &[], &mut sq, network.downlink.delay, 100, true).unwrap();
+
+    // the client's trace is exactly the input trace
+    let client: Vec<i64> = trace
+        .iter()
+        .filter(|e| e.client)
+        .map(|e| e.time.as_nanos())
+        .collect();
+    assert_eq!(client, vec![0, 5000000, 30000000]);
+
+    // the server sent the first received packet before the start of the trace
+    let server: Vec<SimTime> = trace.iter().filter(|e| !e.client).map(|e| e.time).collect();
+    assert_eq!(
+        server,
+        vec![
+            SimTime::from_nanos(-5000000),
+            SimTime::from(Duration::from_millis(10)),
+            SimTime::from(Duration::from_millis(20)),
+        ]
+    );
+    assert_eq!(server[0].since_start(), None);
+    assert_eq!(server[2] - server[0], Duration::from_millis(25));
+}
+
+#[test_log::test]
+fn test_simulator_step() {
+    let m = block_machine();
+    let delay = Duration::from_micros(5);
+    let network = Network::new(delay);
+    let input = "0,sn,100 18,sn,200 25,rn,300 25,rn,300 30,sn,500 35,rn,600";
+    let sq = make_sq(input.to_string(), delay, SimTime::ZERO);
+    let args = SimulatorArgs::new(&network, 40, false);
+
+    // stepping through the simulator is the same as running it to completion
+    let mut simulator = Simulator::new(std::slice::from_ref(&m), &[], sq.clone(), &args).unwrap();
+    let mut stepped = vec![];
+    while let Some(event) = simulator.step().unwrap() {
+        stepped.push(event);
+    }
+    assert_eq!(stepped.len(), 40);
+    assert_eq!(simulator.step().unwrap(), None);
+    stepped.sort_by_key(|e| e.time);
+    let trace = sim_advanced(std::slice::from_ref(&m), &[], &mut sq.clone(), &args).unwrap();
+    assert_eq!(
+        fmt_trace(stepped.clone(), true),
+        fmt_trace(trace.clone(), true)
+    );
+    assert_eq!(fmt_trace(stepped, false), fmt_trace(trace, false));
+
+    // the client blocks from 5us to 10us, and then from 15us to 20us
+    let us = |us: u64| SimTime::from(Duration::from_micros(us));
+    let mut simulator = Simulator::new(std::slice::from_ref(&m), &[], sq, &args).unwrap();
+    let events = simulator.run_until(us(12)).unwrap();
+    assert_eq!(fmt_trace(events, true), "0,sn,100 5,bb 10,be".to_string());
+    assert!(simulator.current_time() <= us(12));
+    assert!(simulator.client(0).blocking_until() <= simulator.current_time());
+    let scheduled: Vec<_> = simulator.client(0).scheduled_actions().values().collect();
+    assert_eq!(scheduled.len(), 1);
+    assert_eq!(scheduled[0].time(), us(15));
+    assert!(matches!(
+        scheduled[0].action(),
+        Some(Action::BlockOutgoing { .. })
+    ));
+
+    simulator.run_until(us(17)).unwrap();
+    assert_eq!(simulator.current_time(), us(15));
+    assert_eq!(simulator.client(0).blocking_until(), us(20));
+    assert!(simulator.server(0).scheduled_actions().is_empty());
+}
+
+#[test_log::test]
+fn test_simulator_into_trace() {
+    // the block machine never stops, so neither does the simulator
+    let m = block_machine();
+    let delay = Duration::from_micros(5);
+    let network = Network::new(delay);
+    let input = "0,sn,100 18,sn,200 25,rn,300 25,rn,300 30,sn,500 35,rn,600";
+    let sq = make_sq(input.to_string(), delay, SimTime::ZERO);
+    let args = SimulatorArgs::new(&network, 0, false);
+
+    let simulator = Simulator::new(std::slice::from_ref(&m), &[], sq.clone(), &args).unwrap();
+    let trace: Vec<SimEvent> = simulator
+        .into_trace()
+        .take(100)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(trace.len(), 100);
+    assert!(trace.windows(2).all(|w| w[0].time <= w[1].time));
+
+    // without integration delays, events are simulated in time order
+    let args = SimulatorArgs::new(&network, 100, false);
+    let expected = sim_advanced(std::slice::from_ref(&m), &[], &mut sq.clone(), &args).unwrap();
+    assert_eq!(
+        fmt_trace(trace.clone(), true),
+        fmt_trace(expected.clone(), true)
+    );
+    assert_eq!(fmt_trace(trace, false), fmt_trace(expected, false));
+}
+
+#[test_log::test]
+fn test_stop_on_sim_time() {
+    // the block machine never stops, so only the time limits stop the
+    // simulator
+    let m = block_machine();
+    let delay = Duration::from_micros(5);
+    let network = Network::new(delay);
+    let input = "0,sn,100 18,sn,200 25,rn,300 25,rn,300 30,sn,500 35,rn,600";
+    let run = |max_sim_duration, stop_after_last_input| {
+        let mut sq = make_sq(input.to_string(), delay, SimTime::ZERO);
+        let mut args = SimulatorArgs::new(&network, 0, false);
+        args.max_sim_duration = max_sim_duration;
+        args.stop_after_last_input = stop_after_last_input;
+        let trace = sim_advanced(std::slice::from_ref(&m), &[], &mut sq, &args).unwrap();
+        fmt_trace(trace, true)
+    };
+
+    assert_eq!(
+        run(Some(Duration::from_micros(27)), None),
+        "0,sn,100 5,bb 10,be 15,bb 20,sn,200 20,be 25,rn,300 25,rn,300 25,bb"
+    );
+    // the last input events are sent at 30us, by both the client and server
+    assert_eq!(
+        run(None, Some(Duration::from_micros(10))),
+        "0,sn,100 5,bb 10,be 15,bb 20,sn,200 20,be 25,rn,300 25,rn,300 25,bb 30,sn,500 30,be 35,rn,600 35,bb 40,be"
+    );
+    // the earliest limit stops the simulator
+    assert_eq!(
+        run(
+            Some(Duration::from_micros(12)),
+            Some(Duration::from_micros(10))
+        ),
+        "0,sn,100 5,bb 10,be"
+    );
+}
+
+#[test_log::test]
+fn test_causal_replay() {
+    // a trace recorded over a network with 10ms delay, simulated over a
+    // network with 20ms delay
+    let recorded = Network::new(Duration::from_millis(10));
+    let raw_trace = "0,s,100\n30000000,r,200\n50000000,s,300\n";
+    let run = |causal: bool| {
+        let mut args = ParseArgs::new(&recorded);
+        args.causal = causal;
+        let mut sq = parse_trace_advanced(raw_trace, &args).unwrap();
+        let trace = sim(&[], &[], &mut sq, Duration::from_millis(20), 0, true).unwrap();
+        (fmt_trace(trace.clone(), true), fmt_trace(trace, false))
+    };
+
+    // the server's response and the client's next request are pinned to
+    // their times in the trace
+    assert_eq!(
+        run(false),
+        (
+            "0,sn,100 40000,rn,200 50000,sn,300".to_string(),
+            "20000,sn,200 20000,rn,100 70000,rn,300".to_string()
+        )
+    );
+    // the server responds 10ms after receiving the request, and the client
+    // sends its next request 20ms after receiving the response
+    assert_eq!(
+        run(true),
+        (
+            "0,sn,100 50000,rn,200 70000,sn,300".to_string(),
+            "20000,rn,100 30000,sn,200 90000,rn,300".to_string()
+        )
+    );
+
+    // only the first request is queued, the rest waits for it
+    let mut args = ParseArgs::new(&recorded);
+    args.causal = true;
+    let sq = parse_trace_advanced(raw_trace, &args).unwrap();
+    assert_eq!(sq.len(), 1);
+    assert_eq!(sq.dependent_len(), 2);
+}
+
+#[test_log::test]
+fn test_causal_replay_loss() {
+    // every packet sent by the client is lost, and never sent again
+    let mut network = Network::new(Duration::from_millis(10));
+    network.uplink.loss = Some(LossModel::Bernoulli { p: 1.0 });
+    let raw_trace = "0,s,100\n30000000,r,200\n50000000,s,300\n";
+    let mut parse_args = ParseArgs::new(&network);
+    parse_args.causal = true;
+    let mut sq = parse_trace_advanced(raw_trace, &parse_args).unwrap();
+
+    // the block machine never stops, so the simulator only stops after the
+    // last input event
+    let m = block_machine();
+    let mut args = SimulatorArgs::new(&network, 0, true);
+    args.stop_after_last_input = Some(Duration::from_millis(1));
+    let trace = sim_advanced(&[m], &[], &mut sq, &args).unwrap();
+
+    // the server responds 10ms after the lost request would have arrived,
+    // and the client sends its next (lost) request 20ms after the response
+    assert_eq!(trace.len(), 4);
+    assert_eq!(
+        (fmt_trace(trace.clone(), true), fmt_trace(trace, false)),
+        (
+            "0,sn,100 30000,rn,200 50000,sn,300".to_string(),
+            "20000,sn,200".to_string()
+        )
+    );
+    assert_eq!(sq.dependent_len(), 0);
+}
@@ -0,0 +1,96 @@
+use std::{str::FromStr, time::Duration};
+
+use maybenot::machine::Machine;
+use maybenot_simulator::{
+    format_observed,
+    network::{LossModel, Network},
+    parse_trace,
+    relay::Relay,
+    tap::Tap,
+    SimError, Simulator, SimulatorArgs,
+};
+
+// run the simulation to completion, returning the packets seen at each tap
+fn simulate(raw_trace: &str, machines: &[Machine], args: &SimulatorArgs) -> Vec<String> {
+    let path = Network::new(Duration::from_millis(10));
+    let sq = parse_trace(raw_trace, &path).unwrap();
+    let mut simulator = Simulator::new(machines, &[], sq, args).unwrap();
+    while simulator.step().unwrap().is_some() {}
+    (0..args.taps.len())
+        .map(|i| format_observed(simulator.observed(i)))
+        .collect()
+}
+
+#[test_log::test]
+fn test_tap_client_and_server() {
+    // sends one padding packet of 1000 bytes 20 ms after the first non-padding
+    // sent, as in the example in the crate documentation
+    let m = "789cedcfc10900200805506d82b6688c1caf5bc3b54823f4a1a2a453b7021ff8ff49\
+    41261f685323426187f8d3f9cceb18039205b9facab8914adf9d6d9406142f07f0";
+    let machines = [Machine::from_str(m).unwrap()];
+
+    let network = Network::new(Duration::from_millis(10));
+    let mut args = SimulatorArgs::new(&network, 0, true);
+    args.max_padding_frac_client = 1.0;
+    let taps = [Tap::Client, Tap::Server];
+    args.taps = &taps;
+
+    // the padding looks like any other packet on the wire, and is seen at
+    // the server 10 ms after it left the client
+    let observed = simulate("0,s,100\n30000000,r,100\n", &machines, &args);
+    assert_eq!(observed[0], "0,s,100\n20000000,s,1000\n30000000,r,100\n");
+    assert_eq!(observed[1], "0,s,100\n10000000,r,100\n20000000,s,1000\n");
+}
+
+#[test_log::test]
+fn test_tap_relay_and_loss() {
+    // a relay halfway, 5 ms from both the client and the server
+    let network = Network::new(Duration::from_millis(5));
+    let relays = [Relay::new(&network)];
+    let mut args = SimulatorArgs::new(&network, 0, true);
+    args.relays = &relays;
+    let taps = [Tap::Client, Tap::Relay(1), Tap::Server];
+    args.taps = &taps;
+    let observed = simulate("0,s,100\n30000000,r,100\n", &[], &args);
+    assert_eq!(
+        observed,
+        vec![
+            "0,s,100\n30000000,r,100\n",
+            "0,s,100\n20000000,r,100\n",
+            "0,s,100\n10000000,r,100\n"
+        ]
+    );
+
+    // a packet lost on the way is only seen before it is lost
+    let mut network = Network::new(Duration::from_millis(10));
+    network.uplink.loss = Some(LossModel::Bernoulli { p: 1.0 });
+    let mut args = SimulatorArgs::new(&network, 0, true);
+    let taps = [Tap::Client, Tap::Server];
+    args.taps = &taps;
+    let observed = simulate("0,s,100\n", &[], &args);
+    assert_eq!(observed, vec!["0,s,100\n", ""]);
+}
+
+#[test_log::test]
+fn test_tap_invalid_relay() {
+    let network = Network::new(Duration::from_millis(5));
+    let relays = [Relay::new(&network)];
+    let invalid = |relays, tap| {
+        let mut args = SimulatorArgs::new(&network, 0, true);
+        args.relays = relays;
+        let taps = [Tap::Client, tap];
+        args.taps = &taps;
+        let sq = parse_trace("0,s,100\n", &network).unwrap();
+        matches!(
+            Simulator::new(&[], &[], sq, &args),
+            Err(SimError::InvalidTap(t)) if t == tap
+        )
+    };
+
+    // relays are numbered from 1, and without relays only the client and
+    // server can be tapped
+    assert!(!invalid(&relays, Tap::Relay(1)));
+    assert!(invalid(&relays, Tap::Relay(0)));
+    assert!(invalid(&relays, Tap::Relay(2)));
+    assert!(invalid(&[], Tap::Relay(1)));
+}
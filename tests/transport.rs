@@ -0,0 +1,82 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use maybenot::framework::TriggerEvent;
+use maybenot_simulator::{
+    network::{LossModel, Network},
+    parse_trace, sim_advanced,
+    transport::Tcp,
+    SimEvent, SimulatorArgs,
+};
+
+// the number of non-padding packets received by the client at each time (ms)
+fn received(trace: &[SimEvent]) -> BTreeMap<i64, usize> {
+    let mut received = BTreeMap::new();
+    for e in trace
+        .iter()
+        .filter(|e| e.client && matches!(e.event, TriggerEvent::NonPaddingRecv { .. }))
+    {
+        *received.entry(e.time.as_millis()).or_default() += 1;
+    }
+    received
+}
+
+// a request from the client answered by a burst of 30 packets from the server
+fn burst() -> String {
+    let mut trace = "0,s,100\n".to_string();
+    for _ in 0..30 {
+        trace.push_str("20000000,r,1000\n");
+    }
+    trace
+}
+
+#[test_log::test]
+fn test_tcp_slow_start() {
+    let network = Network::new(Duration::from_millis(10));
+
+    // without a transport, the burst arrives at once
+    let mut sq = parse_trace(&burst(), &network).unwrap();
+    let args = SimulatorArgs::new(&network, 0, true);
+    let trace = sim_advanced(&[], &[], &mut sq, &args).unwrap();
+    assert_eq!(received(&trace), BTreeMap::from([(20, 30)]));
+
+    // with a transport, the first 10 packets fill the initial window, and
+    // then the window doubles every round trip
+    let mut sq = parse_trace(&burst(), &network).unwrap();
+    let mut args = SimulatorArgs::new(&network, 0, true);
+    args.transport = Some(Tcp::new());
+    let trace = sim_advanced(&[], &[], &mut sq, &args).unwrap();
+    assert_eq!(received(&trace), BTreeMap::from([(20, 10), (40, 20)]));
+
+    // with an initial window of 2
+    let mut sq = parse_trace(&burst(), &network).unwrap();
+    args.transport = Some(Tcp {
+        initial_window: 2,
+        ..Tcp::new()
+    });
+    let trace = sim_advanced(&[], &[], &mut sq, &args).unwrap();
+    assert_eq!(
+        received(&trace),
+        BTreeMap::from([(20, 2), (40, 4), (60, 8), (80, 16)])
+    );
+}
+
+#[test_log::test]
+fn test_tcp_loss_stalls_window() {
+    // every packet from the server is lost, and never retransmitted
+    let mut network = Network::new(Duration::from_millis(10));
+    network.downlink.loss = Some(LossModel::Bernoulli { p: 1.0 });
+    let mut sq = parse_trace(&burst(), &network).unwrap();
+    let mut args = SimulatorArgs::new(&network, 0, true);
+    args.transport = Some(Tcp::new());
+    let trace = sim_advanced(&[], &[], &mut sq, &args).unwrap();
+
+    // the server sends its initial window, and then the window halves once
+    // per round trip of losses, down to two packets per round trip
+    let mut sent = BTreeMap::new();
+    for e in trace.iter().filter(|e| !e.client && e.dropped) {
+        *sent.entry(e.time.as_millis()).or_insert(0) += 1;
+    }
+    let mut expected = BTreeMap::from([(10, 10), (30, 5), (190, 1)]);
+    expected.extend((0..7).map(|i| (50 + i * 20, 2)));
+    assert_eq!(sent, expected);
+}